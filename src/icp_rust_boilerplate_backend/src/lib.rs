@@ -1,16 +1,88 @@
 #[macro_use]
 extern crate serde;
-use candid::{Decode, Encode};
-use ic_cdk::api::time;
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, is_controller, time};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::{borrow::Cow, cell::RefCell};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
 
+const NANOS_PER_DAY: u64 = 86_400_000_000_000;
+// Years whose months fit in the u64 nanosecond timestamps, which end in 2554
+const MIN_YEAR: u32 = 1970;
+const MAX_YEAR: u32 = 2553;
+const MAX_PAGE_SIZE: u64 = 100;
+// Bytes of free text in an audit record, the fixed fields fit in the rest
+// of AuditRecord::MAX_SIZE
+const MAX_AUDIT_DETAILS_LEN: usize = 512;
+
+// Entry field limits in bytes. Together they can still exceed
+// PettyCashEntry::MAX_SIZE, so the encoded entry is checked as well.
+const MAX_DESCRIPTION_LEN: usize = 500;
+const MAX_CATEGORY_LEN: usize = 64;
+const MAX_RECEIPT_URL_LEN: usize = 200;
+const MAX_CLIENT_ID_LEN: usize = 64;
+const MAX_ATTACHMENTS: usize = 3;
+const MAX_ATTACHMENT_URL_LEN: usize = 200;
+const MAX_ATTACHMENT_FILENAME_LEN: usize = 100;
+const MAX_MIME_TYPE_LEN: usize = 64;
+// Longest textual form of a principal and of an approval signature
+const MAX_PRINCIPAL_TEXT_LEN: usize = 63;
+const MAX_SIGNATURE_LEN: usize = 128;
+
+// Search limits for find_entries_summing_to
+const MAX_SUM_SEARCH_ENTRIES: usize = 40;
+const MAX_SUM_COMBINATION: u32 = 4;
+const MAX_SUM_RESULTS: usize = 20;
+const MAX_SUM_STEPS: u64 = 200_000;
+
+const OPENING_BALANCE_CATEGORY: &str = "opening balance";
+const ADJUSTMENT_CATEGORY: &str = "adjustment";
+
+const DEFAULT_DISPLAY_PRECISION: u32 = 2;
+const MAX_DISPLAY_PRECISION: u32 = 8;
+
+const DESTRUCTIVE_TOKEN_TTL: u64 = 5 * 60 * 1_000_000_000;
+
+// Bumped whenever LedgerSnapshot changes shape
+const SNAPSHOT_VERSION: u32 = 1;
+
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 struct PettyCashEntry {
+    id: u64,
+    date: u64,
+    description: String,
+    amount: f64,
+    entry_type: TransactionType,
+    category: String,
+    receipt_url: Option<String>,
+    approved_by: Option<String>,
+    approved_at: Option<u64>,
+    // Hex encoded signature the approver attached in approve_entry
+    approval_signature: Option<String>,
+    created_at: u64,
+    updated_at: Option<u64>,
+    // Future-dated entries are stored but do not affect the balance
+    // until they are activated by run_activate_scheduled
+    scheduled: bool,
+    attachments: Vec<Attachment>,
+    // Locked entries cannot be updated or deleted until an admin unlocks them
+    locked: bool,
+    // Set on adjustment entries created for a reconciliation
+    reconciliation_id: Option<u64>,
+    // Client the expense is billed back to
+    billable_to: Option<String>,
+}
+
+// Shape of the entries stored by the first release of the canister
+#[derive(candid::CandidType, Deserialize)]
+struct LegacyPettyCashEntry {
     id: u64,
     date: u64,
     description: String,
@@ -23,26 +95,51 @@ struct PettyCashEntry {
     updated_at: Option<u64>,
 }
 
+impl From<LegacyPettyCashEntry> for PettyCashEntry {
+    fn from(entry: LegacyPettyCashEntry) -> Self {
+        PettyCashEntry {
+            id: entry.id,
+            date: entry.date,
+            description: entry.description,
+            amount: entry.amount,
+            entry_type: entry.entry_type,
+            category: entry.category,
+            receipt_url: entry.receipt_url,
+            approved_by: entry.approved_by,
+            created_at: entry.created_at,
+            updated_at: entry.updated_at,
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Attachment {
+    url: String,
+    filename: String,
+    mime_type: String,
+    size_bytes: u64,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
 enum TransactionType {
+    #[default]
     Debit,  // Pengeluaran
     Credit, // Pengisian kas
 }
 
-impl Default for TransactionType {
-    fn default() -> Self {
-        TransactionType::Debit
-    }
-}
-
 // Implementation for stable storage
 impl Storable for PettyCashEntry {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        // Entries written before the scheduled, attachments and locked fields
+        // existed lack them and are read in their original shape
+        Decode!(bytes.as_ref(), Self)
+            .or_else(|_| Decode!(bytes.as_ref(), LegacyPettyCashEntry).map(Self::from))
+            .unwrap()
     }
 }
 
@@ -51,6 +148,144 @@ impl BoundedStorable for PettyCashEntry {
     const IS_FIXED_SIZE: bool = false;
 }
 
+// Canister wide configuration, kept in a single stable cell
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Settings {
+    admin: Option<Principal>,
+    // Proposed by transfer_admin, becomes the admin once it accepts
+    pending_admin: Option<Principal>,
+    // Monthly debit budget per normalized category
+    budgets: BTreeMap<String, f64>,
+    // Flipping an entry between Debit and Credit clears its approval
+    reapprove_on_type_flip: bool,
+    // Debits may not take the balance below this amount
+    minimum_reserve: f64,
+    // Set once the admin is configured, entries cannot be changed before that
+    initialized: bool,
+    // Designated approver per normalized category
+    category_approvers: BTreeMap<String, Principal>,
+    // Debits are checked against the available instead of the total balance
+    enforce_available_balance: bool,
+    // Outstanding confirmation for a destructive operation
+    destructive_token: Option<DestructiveToken>,
+    // Closed (year, month) periods with their closing report snapshot
+    closed_periods: BTreeMap<(u32, u32), ClosedPeriod>,
+    // Maximum number of entries per normalized category and calendar month
+    category_entry_limits: BTreeMap<String, u64>,
+    // Decimals of exposed balances, DEFAULT_DISPLAY_PRECISION when unset
+    display_precision: Option<u32>,
+    // Parent of a normalized category, e.g. flights under travel
+    category_parents: BTreeMap<String, String>,
+    // Debits above a threshold need a detailed description
+    description_policy: Option<DescriptionPolicy>,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+struct DescriptionPolicy {
+    min_amount: f64,
+    min_length: u32,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ClosedPeriod {
+    closed_at: u64,
+    closed_by: Principal,
+    snapshot: MonthlyReport,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DestructiveToken {
+    token: String,
+    owner: Principal,
+    expires_at: u64,
+}
+
+impl Storable for Settings {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Activate,
+    Approve,
+    CorrectAmount,
+    AdminChange,
+    Configure,
+    Repair,
+    Purge,
+    Lock,
+    Unlock,
+    ClosePeriod,
+    ReopenPeriod,
+    Reconcile,
+    Adjust,
+    Restore,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct AuditRecord {
+    id: u64,
+    action: AuditAction,
+    entry_id: Option<u64>,
+    caller: Principal,
+    timestamp: u64,
+    details: String,
+    // Old and new amount when the action changed an entry's amount
+    amount_change: Option<(f64, f64)>,
+}
+
+impl Storable for AuditRecord {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AuditRecord {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A physical cash count compared against the ledger balance
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Reconciliation {
+    id: u64,
+    counted_amount: f64,
+    expected_balance: f64,
+    // counted_amount - expected_balance at the time of the count
+    difference: f64,
+    note: String,
+    reconciled_at: u64,
+    reconciled_by: Principal,
+}
+
+impl Storable for Reconciliation {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Reconciliation {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
         MemoryManager::init(DefaultMemoryImpl::default())
@@ -70,16 +305,140 @@ thread_local! {
         Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))), 0.0)
             .expect("Cannot create balance cell")
     );
+
+    // Part of the balance earmarked for approved but unpaid commitments
+    static ALLOCATED: RefCell<Cell<f64, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))), 0.0)
+            .expect("Cannot create allocated cell")
+    );
+
+    static SETTINGS: RefCell<Cell<Settings, Memory>> = RefCell::new(
+        Cell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))), Settings::default())
+            .expect("Cannot create settings cell")
+    );
+
+    static AUDIT_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))), 0)
+            .expect("Cannot create an audit counter")
+    );
+
+    static AUDIT_LOG: RefCell<StableBTreeMap<u64, AuditRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+    ));
+
+    static RECONCILIATION_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))), 0)
+            .expect("Cannot create a reconciliation counter")
+    );
+
+    static RECONCILIATIONS: RefCell<StableBTreeMap<u64, Reconciliation, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
+    ));
 }
 
 #[derive(candid::CandidType, Serialize, Deserialize)]
 struct EntryPayload {
+    // Optional transaction date in nanoseconds, defaults to now.
+    // A date in the future schedules the entry instead of applying it.
+    date: Option<u64>,
     description: String,
     amount: f64,
     entry_type: TransactionType,
     category: String,
     receipt_url: Option<String>,
-    approved_by: Option<String>,
+    // None adds no attachments, or keeps the current ones on update
+    attachments: Option<Vec<Attachment>>,
+    billable_to: Option<String>,
+}
+
+// Full ledger state for off-chain backups. The audit log is not included.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct LedgerSnapshot {
+    version: u32,
+    taken_at: u64,
+    entries: Vec<PettyCashEntry>,
+    reconciliations: Vec<Reconciliation>,
+    balance: f64,
+    allocated: f64,
+    next_entry_id: u64,
+    next_reconciliation_id: u64,
+    settings: Settings,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct VerifyResult {
+    exists: bool,
+    amount_matches: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PagedEntries {
+    entries: Vec<PettyCashEntry>,
+    total: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct BudgetVariance {
+    category: String,
+    budget: f64,
+    actual_spent: f64,
+    variance: f64,
+    variance_percent: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct CategoryGroup {
+    category: String,
+    entries: Vec<PettyCashEntry>,
+    total_debit: f64,
+    total_credit: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct CategorySummary {
+    category: String,
+    parent: Option<String>,
+    entry_count: u64,
+    total_debit: f64,
+    total_credit: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct BurndownPoint {
+    day: u32,
+    // UTC midnight of the day
+    date: u64,
+    cumulative_spent: f64,
+    budget: f64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ApproverSummary {
+    approver: String,
+    count: u64,
+    total_amount: f64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MonthlyReport {
+    year: u32,
+    month: u32,
+    total_debit: f64,
+    total_credit: f64,
+    entry_count: u64,
+    // Balance after every active entry dated up to the end of the month
+    closing_balance: f64,
+    // True when served from the snapshot taken at period close
+    from_snapshot: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct CounterRepair {
+    counter: String,
+    previous: f64,
+    recomputed: f64,
 }
 
 #[derive(candid::CandidType, Deserialize, Serialize)]
@@ -87,9 +446,72 @@ enum Error {
     NotFound { msg: String },
     InvalidAmount { msg: String },
     InsufficientFunds { msg: String },
+    Unauthorized { msg: String },
+    InvalidInput { msg: String },
+    NotInitialized { msg: String },
+    EntryNotEditable { msg: String },
+    RateLimited { msg: String },
+}
+
+// The admin is fixed at install time so there is no window in which an
+// arbitrary caller could claim it. Deployers pass the owner principal as the
+// init argument, when omitted the installing principal becomes the admin:
+//
+//   dfx deploy icp_rust_boilerplate_backend --argument '(opt principal "<owner>")'
+#[ic_cdk::init]
+fn init(admin: Option<Principal>) {
+    let admin = admin.unwrap_or_else(caller);
+    if admin == Principal::anonymous() {
+        ic_cdk::trap("The anonymous principal cannot be the admin");
+    }
+    update_settings(|settings| {
+        settings.admin = Some(admin);
+        settings.initialized = true;
+    });
+}
+
+// Workflow status derived from an entry's fields
+#[derive(candid::CandidType, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum EntryStatus {
+    Scheduled, // future-dated, not applied to the balance yet
+    Pending,   // applied but not approved
+    Approved,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default)]
+enum DateFormat {
+    #[default]
+    Iso8601,  // 2024-03-31T14:05:00Z
+    DdMmYyyy, // 31/03/2024
+    MmDdYyyy, // 03/31/2024
+}
+
+#[derive(Serialize)]
+struct ExportRow {
+    id: u64,
+    date: String,
+    description: String,
+    amount: f64,
+    entry_type: String,
+    category: String,
+    receipt_url: Option<String>,
+    approved_by: Option<String>,
+    approval_signature: Option<String>,
+    attachments: Vec<Attachment>,
+    billable_to: Option<String>,
 }
 
 // Query Methods
+#[ic_cdk::query]
+fn get_admin() -> Option<Principal> {
+    get_settings().admin
+}
+
+#[ic_cdk::query]
+fn get_pending_admin() -> Option<Principal> {
+    get_settings().pending_admin
+}
+
 #[ic_cdk::query]
 fn get_entry(id: u64) -> Result<PettyCashEntry, Error> {
     match _get_entry(&id) {
@@ -100,9 +522,38 @@ fn get_entry(id: u64) -> Result<PettyCashEntry, Error> {
     }
 }
 
+// Checks that an entry exists and has the expected amount without returning
+// the whole entry. A missing entry never matches.
+#[ic_cdk::query]
+fn verify_entry(id: u64, expected_amount: f64) -> VerifyResult {
+    match _get_entry(&id) {
+        Some(entry) => VerifyResult {
+            exists: true,
+            amount_matches: amount_equals(entry.amount, expected_amount),
+        },
+        None => VerifyResult {
+            exists: false,
+            amount_matches: false,
+        },
+    }
+}
+
+// Balance queries are rounded to the display precision, all arithmetic
+// inside the canister uses the raw stored values
 #[ic_cdk::query]
 fn get_current_balance() -> f64 {
-    BALANCE.with(|balance| *balance.borrow().get())
+    round_for_display(raw_balance())
+}
+
+#[ic_cdk::query]
+fn get_allocated_balance() -> f64 {
+    round_for_display(raw_allocated())
+}
+
+// Balance not earmarked by allocate
+#[ic_cdk::query]
+fn get_available_balance() -> f64 {
+    round_for_display(raw_available())
 }
 
 #[ic_cdk::query]
@@ -111,117 +562,849 @@ fn get_entries_by_date_range(start_date: u64, end_date: u64) -> Vec<PettyCashEnt
         storage
             .borrow()
             .iter()
+            .filter(|(_, entry)| !entry.scheduled)
             .filter(|(_, entry)| entry.date >= start_date && entry.date <= end_date)
             .map(|(_, entry)| entry)
             .collect()
     })
 }
 
-// Update Methods
-#[ic_cdk::update]
-fn add_entry(payload: EntryPayload) -> Result<PettyCashEntry, Error> {
-    // Validate amount
-    if payload.amount <= 0.0 {
-        return Err(Error::InvalidAmount {
-            msg: "Amount must be greater than 0".to_string(),
-        });
-    }
+// Same selection as get_entries_by_date_range, ids only in ascending order
+#[ic_cdk::query]
+fn get_entry_ids_by_date_range(start_date: u64, end_date: u64) -> Vec<u64> {
+    PETTY_CASH_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, entry)| !entry.scheduled)
+            .filter(|(_, entry)| entry.date >= start_date && entry.date <= end_date)
+            .map(|(id, _)| id)
+            .collect()
+    })
+}
 
-    // Check if sufficient funds for debit transactions
-    if matches!(payload.entry_type, TransactionType::Debit) {
-        let current_balance = get_current_balance();
-        if current_balance < payload.amount {
-            return Err(Error::InsufficientFunds {
-                msg: format!(
-                    "Insufficient funds. Current balance: {}, Required: {}",
-                    current_balance, payload.amount
-                ),
-            });
+// Page through the entries of one category, the limit is capped at MAX_PAGE_SIZE
+#[ic_cdk::query]
+fn get_entries_by_category_paged(category: String, offset: u64, limit: u64) -> PagedEntries {
+    let category = normalize_category(&category);
+    let limit = limit.min(MAX_PAGE_SIZE);
+    let mut page = PagedEntries {
+        entries: Vec::new(),
+        total: 0,
+    };
+
+    PETTY_CASH_STORAGE.with(|storage| {
+        for (_, entry) in storage.borrow().iter() {
+            if entry.scheduled || normalize_category(&entry.category) != category {
+                continue;
+            }
+            if page.total >= offset && page.total < offset.saturating_add(limit) {
+                page.entries.push(entry);
+            }
+            page.total += 1;
         }
-    }
+    });
 
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
+    page
+}
 
-    let entry = PettyCashEntry {
-        id,
-        date: time(),
-        description: payload.description,
-        amount: payload.amount,
-        entry_type: payload.entry_type.clone(),
-        category: payload.category,
-        receipt_url: payload.receipt_url,
-        approved_by: payload.approved_by,
-        created_at: time(),
-        updated_at: None,
-    };
+// Debits of a date range that break the description policy, e.g. entries
+// created before it was enabled. Empty when no policy is set.
+#[ic_cdk::query]
+fn get_under_documented_entries(start_date: u64, end_date: u64) -> Vec<PettyCashEntry> {
+    match get_settings().description_policy {
+        Some(policy) => get_entries_by_date_range(start_date, end_date)
+            .into_iter()
+            .filter(|entry| {
+                violates_description_policy(
+                    &policy,
+                    entry.entry_type,
+                    entry.amount,
+                    &entry.description,
+                )
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
 
-    // Update balance
-    let balance_change = match payload.entry_type {
-        TransactionType::Credit => payload.amount,
-        TransactionType::Debit => -payload.amount,
+// Page through the entries in one status, the limit is capped at MAX_PAGE_SIZE
+#[ic_cdk::query]
+fn get_entries_by_status(status: EntryStatus, offset: u64, limit: u64) -> PagedEntries {
+    let limit = limit.min(MAX_PAGE_SIZE);
+    let mut page = PagedEntries {
+        entries: Vec::new(),
+        total: 0,
     };
 
-    BALANCE.with(|balance| {
-        let current_balance = *balance.borrow().get();
-        balance
-            .borrow_mut()
-            .set(current_balance + balance_change)
-            .expect("Cannot update balance");
+    PETTY_CASH_STORAGE.with(|storage| {
+        for (_, entry) in storage.borrow().iter() {
+            if entry_status(&entry) != status {
+                continue;
+            }
+            if page.total >= offset && page.total < offset.saturating_add(limit) {
+                page.entries.push(entry);
+            }
+            page.total += 1;
+        }
     });
 
-    do_insert(&entry);
-    Ok(entry)
+    page
 }
 
-#[ic_cdk::update]
-fn update_entry(id: u64, payload: EntryPayload) -> Result<PettyCashEntry, Error> {
-    match PETTY_CASH_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut entry) => {
-            // Reverse the previous balance change
-            let old_balance_change = match entry.entry_type {
-                TransactionType::Credit => -entry.amount,
-                TransactionType::Debit => entry.amount,
-            };
-
-            // Calculate new balance change
-            let new_balance_change = match payload.entry_type {
-                TransactionType::Credit => payload.amount,
-                TransactionType::Debit => -payload.amount,
-            };
+#[ic_cdk::query]
+fn get_scheduled_entries() -> Vec<PettyCashEntry> {
+    PETTY_CASH_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, entry)| entry.scheduled)
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
 
-            // Update balance
-            BALANCE.with(|balance| {
-                let current_balance = *balance.borrow().get();
-                let new_balance = current_balance + old_balance_change + new_balance_change;
-                
-                if new_balance < 0.0 {
-                    return Err(Error::InsufficientFunds {
-                        msg: "Update would result in negative balance".to_string(),
-                    });
-                }
+// Entries dated after now. Besides scheduled entries awaiting activation this
+// catches date typos such as a year 2099 date. Entries that are due but not
+// yet activated are listed by get_scheduled_entries instead.
+#[ic_cdk::query]
+fn get_future_dated_entries() -> Vec<PettyCashEntry> {
+    let now = time();
+    PETTY_CASH_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, entry)| entry.date > now)
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
 
-                balance
-                    .borrow_mut()
-                    .set(new_balance)
-                    .expect("Cannot update balance");
-                Ok(())
-            })?;
+// Average debit per UTC day, counting both the start and end day
+#[ic_cdk::query]
+fn get_average_daily_spend(start_date: u64, end_date: u64) -> f64 {
+    if end_date < start_date {
+        return 0.0;
+    }
 
-            // Update entry fields
-            entry.description = payload.description;
-            entry.amount = payload.amount;
-            entry.entry_type = payload.entry_type;
+    let total_debit: f64 = get_entries_by_date_range(start_date, end_date)
+        .iter()
+        .filter(|entry| matches!(entry.entry_type, TransactionType::Debit))
+        .map(|entry| entry.amount)
+        .sum();
+    let days = day_index(end_date) - day_index(start_date) + 1;
+
+    safe_div(total_debit, days as f64)
+}
+
+#[ic_cdk::query]
+fn export_entries_csv(start_date: u64, end_date: u64, date_format: Option<DateFormat>) -> String {
+    let date_format = date_format.unwrap_or_default();
+    let mut csv = String::from(
+        "id,date,description,amount,entry_type,category,receipt_url,approved_by,\
+         approval_signature,attachments,billable_to\n",
+    );
+    for entry in get_entries_by_date_range(start_date, end_date) {
+        let row = export_row(&entry, date_format);
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{}\n",
+            row.id,
+            row.date,
+            csv_field(&row.description),
+            row.amount,
+            row.entry_type,
+            csv_field(&row.category),
+            csv_field(row.receipt_url.as_deref().unwrap_or("")),
+            csv_field(row.approved_by.as_deref().unwrap_or("")),
+            row.approval_signature.as_deref().unwrap_or(""),
+            csv_field(
+                &row.attachments
+                    .iter()
+                    .map(|attachment| attachment.url.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            csv_field(row.billable_to.as_deref().unwrap_or("")),
+        ));
+    }
+    csv
+}
+
+// Debits billable to one client, in date order, with the columns an invoice
+// needs. The receipt is the receipt url or else the first attachment.
+#[ic_cdk::query]
+fn export_billable_csv(client: String, start_date: u64, end_date: u64) -> String {
+    let client = normalize_client(Some(client));
+    let mut entries: Vec<PettyCashEntry> = get_entries_by_date_range(start_date, end_date)
+        .into_iter()
+        .filter(|entry| matches!(entry.entry_type, TransactionType::Debit))
+        .filter(|entry| client.is_some() && entry.billable_to == client)
+        .collect();
+    entries.sort_by_key(|entry| (entry.date, entry.id));
+
+    let mut csv = String::from("date,description,amount,receipt\n");
+    for entry in entries {
+        let receipt = entry
+            .receipt_url
+            .as_deref()
+            .or_else(|| {
+                entry
+                    .attachments
+                    .first()
+                    .map(|attachment| attachment.url.as_str())
+            })
+            .unwrap_or("");
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            format_date(entry.date, DateFormat::Iso8601),
+            csv_field(&entry.description),
+            entry.amount,
+            csv_field(receipt),
+        ));
+    }
+    csv
+}
+
+#[ic_cdk::query]
+fn export_entries_json(start_date: u64, end_date: u64, date_format: Option<DateFormat>) -> String {
+    let date_format = date_format.unwrap_or_default();
+    let rows: Vec<ExportRow> = get_entries_by_date_range(start_date, end_date)
+        .iter()
+        .map(|entry| export_row(entry, date_format))
+        .collect();
+    serde_json::to_string(&rows).expect("Cannot serialize entries")
+}
+
+// Candid encoded LedgerSnapshot of the whole ledger, restored with
+// import_snapshot. Outstanding destructive tokens are left out.
+#[ic_cdk::query]
+fn export_snapshot() -> Vec<u8> {
+    let mut settings = get_settings();
+    settings.destructive_token = None;
+    let snapshot = LedgerSnapshot {
+        version: SNAPSHOT_VERSION,
+        taken_at: time(),
+        entries: PETTY_CASH_STORAGE
+            .with(|storage| storage.borrow().iter().map(|(_, entry)| entry).collect()),
+        reconciliations: RECONCILIATIONS.with(|reconciliations| {
+            reconciliations
+                .borrow()
+                .iter()
+                .map(|(_, reconciliation)| reconciliation)
+                .collect()
+        }),
+        balance: raw_balance(),
+        allocated: raw_allocated(),
+        next_entry_id: ID_COUNTER.with(|counter| *counter.borrow().get()),
+        next_reconciliation_id: RECONCILIATION_ID_COUNTER.with(|counter| *counter.borrow().get()),
+        settings,
+    };
+    Encode!(&snapshot).expect("Cannot encode snapshot")
+}
+
+#[ic_cdk::query]
+fn get_category_budgets() -> Vec<(String, f64)> {
+    get_settings().budgets.into_iter().collect()
+}
+
+// Budget minus actual debits per category for the current UTC month.
+// Categories that have spending but no budget show up with a zero budget.
+#[ic_cdk::query]
+fn get_budget_variance() -> Vec<BudgetVariance> {
+    let (year, month, _) = civil_from_days(day_index(time()));
+    let (start_date, end_date) = month_bounds(year, month);
+
+    let spent = debits_by_category(start_date, end_date);
+
+    let budgets = get_settings().budgets;
+    let mut categories: Vec<&String> = budgets.keys().chain(spent.keys()).collect();
+    categories.sort();
+    categories.dedup();
+
+    categories
+        .into_iter()
+        .map(|category| {
+            let budget = budgets.get(category).copied().unwrap_or(0.0);
+            let actual_spent = spent.get(category).copied().unwrap_or(0.0);
+            let variance = budget - actual_spent;
+            BudgetVariance {
+                category: category.clone(),
+                budget,
+                actual_spent,
+                variance,
+                variance_percent: safe_div(variance, budget) * 100.0,
+            }
+        })
+        .collect()
+}
+
+// Distinct descriptions starting with prefix, ignoring case, in the order
+// they were first entered. The limit is capped at MAX_PAGE_SIZE.
+#[ic_cdk::query]
+fn search_descriptions(prefix: String, limit: u64) -> Vec<String> {
+    let prefix = prefix.to_lowercase();
+    let limit = limit.min(MAX_PAGE_SIZE) as usize;
+    let mut seen = BTreeSet::new();
+    let mut descriptions = Vec::new();
+
+    PETTY_CASH_STORAGE.with(|storage| {
+        for (_, entry) in storage.borrow().iter() {
+            if descriptions.len() >= limit {
+                break;
+            }
+            let key = entry.description.to_lowercase();
+            if key.starts_with(&prefix) && seen.insert(key) {
+                descriptions.push(entry.description);
+            }
+        }
+    });
+
+    descriptions
+}
+
+// Number of entries created in each UTC hour of the day, for entries whose
+// created_at falls inside the range
+#[ic_cdk::query]
+fn get_creation_hour_distribution(start_date: u64, end_date: u64) -> [u64; 24] {
+    let mut distribution = [0; 24];
+    PETTY_CASH_STORAGE.with(|storage| {
+        for (_, entry) in storage.borrow().iter() {
+            if entry.created_at >= start_date && entry.created_at <= end_date {
+                distribution[hour_of_day(entry.created_at)] += 1;
+            }
+        }
+    });
+    distribution
+}
+
+// Receipt urls (including attachment urls) shared by more than one entry,
+// which usually means the same receipt was claimed twice
+#[ic_cdk::query]
+fn find_duplicate_receipts() -> Vec<(String, Vec<u64>)> {
+    let mut usages: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+    PETTY_CASH_STORAGE.with(|storage| {
+        for (id, entry) in storage.borrow().iter() {
+            let urls = entry
+                .receipt_url
+                .iter()
+                .chain(entry.attachments.iter().map(|attachment| &attachment.url));
+            for url in urls {
+                let ids = usages.entry(normalize_url(url)).or_default();
+                // An entry listing the same url twice is not a double claim
+                if ids.last() != Some(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+    });
+
+    usages
+        .into_iter()
+        .filter(|(url, ids)| !url.is_empty() && ids.len() > 1)
+        .collect()
+}
+
+// Entries dated in the range whose audit history records at least one
+// change of the amount, through update_entry or correct_amount
+#[ic_cdk::query]
+fn get_entries_with_amount_changes(start_date: u64, end_date: u64) -> Vec<PettyCashEntry> {
+    let changed_ids: BTreeSet<u64> = AUDIT_LOG.with(|log| {
+        log.borrow()
+            .iter()
+            .filter(|(_, record)| record.amount_change.is_some())
+            .filter_map(|(_, record)| record.entry_id)
+            .collect()
+    });
+
+    changed_ids
+        .into_iter()
+        .filter_map(|id| _get_entry(&id))
+        .filter(|entry| entry.date >= start_date && entry.date <= end_date)
+        .collect()
+}
+
+// Audit records in chronological order, optionally narrowed to one action.
+// The time window is inclusive and the limit is capped at MAX_PAGE_SIZE.
+#[ic_cdk::query]
+fn get_audit_log_filtered(
+    action: Option<AuditAction>,
+    start: u64,
+    end: u64,
+    offset: u64,
+    limit: u64,
+) -> Vec<AuditRecord> {
+    AUDIT_LOG.with(|log| {
+        log.borrow()
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| action.is_none() || action == Some(record.action))
+            .filter(|record| record.timestamp >= start && record.timestamp <= end)
+            .skip(usize::try_from(offset).unwrap_or(usize::MAX))
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .collect()
+    })
+}
+
+// Entries of a date range grouped under their normalized category, ordered by
+// category name, each group sorted by date and carrying its subtotals
+#[ic_cdk::query]
+fn get_grouped_statement(start_date: u64, end_date: u64) -> Vec<CategoryGroup> {
+    let mut groups: BTreeMap<String, CategoryGroup> = BTreeMap::new();
+    for entry in get_entries_by_date_range(start_date, end_date) {
+        let category = normalize_category(&entry.category);
+        let group = groups
+            .entry(category.clone())
+            .or_insert_with(|| CategoryGroup {
+                category,
+                ..Default::default()
+            });
+        match entry.entry_type {
+            TransactionType::Debit => group.total_debit += entry.amount,
+            TransactionType::Credit => group.total_credit += entry.amount,
+        }
+        group.entries.push(entry);
+    }
+
+    groups
+        .into_values()
+        .map(|mut group| {
+            group.entries.sort_by_key(|entry| (entry.date, entry.id));
+            group
+        })
+        .collect()
+}
+
+// Totals per normalized category for a date range, ordered by category name.
+// With rollup every category's totals are also added to all of its ancestors,
+// so a parent reports its own entries plus those of its children.
+#[ic_cdk::query]
+fn get_category_summary(start_date: u64, end_date: u64, rollup: bool) -> Vec<CategorySummary> {
+    let parents = get_settings().category_parents;
+    let mut summaries: BTreeMap<String, CategorySummary> = BTreeMap::new();
+    for entry in get_entries_by_date_range(start_date, end_date) {
+        let category = normalize_category(&entry.category);
+        let mut targets = vec![category.clone()];
+        if rollup {
+            let mut current = &category;
+            while let Some(parent) = parents.get(current) {
+                targets.push(parent.clone());
+                current = parent;
+            }
+        }
+
+        for category in targets {
+            let summary = summaries
+                .entry(category.clone())
+                .or_insert_with(|| CategorySummary {
+                    parent: parents.get(&category).cloned(),
+                    category,
+                    ..Default::default()
+                });
+            summary.entry_count += 1;
+            match entry.entry_type {
+                TransactionType::Debit => summary.total_debit += entry.amount,
+                TransactionType::Credit => summary.total_credit += entry.amount,
+            }
+        }
+    }
+
+    summaries.into_values().collect()
+}
+
+// Cumulative debits of a category for each day of a month next to its
+// monthly budget. An invalid year or month yields no points.
+#[ic_cdk::query]
+fn get_budget_burndown(category: String, year: u32, month: u32) -> Vec<BurndownPoint> {
+    if validate_period(year, month).is_err() {
+        return Vec::new();
+    }
+
+    let category = normalize_category(&category);
+    let budget = get_settings()
+        .budgets
+        .get(&category)
+        .copied()
+        .unwrap_or(0.0);
+    let (start_date, end_date) = month_bounds(year as u64, month);
+    let days_in_month = day_index(end_date) - day_index(start_date) + 1;
+
+    let mut daily_spent = vec![0.0; days_in_month as usize];
+    for entry in get_entries_by_date_range(start_date, end_date) {
+        if matches!(entry.entry_type, TransactionType::Debit)
+            && normalize_category(&entry.category) == category
+        {
+            daily_spent[(day_index(entry.date) - day_index(start_date)) as usize] += entry.amount;
+        }
+    }
+
+    let mut cumulative_spent = 0.0;
+    daily_spent
+        .into_iter()
+        .enumerate()
+        .map(|(offset, spent)| {
+            cumulative_spent += spent;
+            BurndownPoint {
+                day: offset as u32 + 1,
+                date: start_date + offset as u64 * NANOS_PER_DAY,
+                cumulative_spent,
+                budget,
+            }
+        })
+        .collect()
+}
+
+// The UTC day (as its midnight timestamp) with the largest net debit in the
+// range. Days where credits cover the debits are not outflows, so a range
+// without any net outflow returns None. Ties go to the earliest day.
+#[ic_cdk::query]
+fn get_max_daily_outflow(start_date: u64, end_date: u64) -> Option<(u64, f64)> {
+    let mut daily_outflow: BTreeMap<u64, f64> = BTreeMap::new();
+    for entry in get_entries_by_date_range(start_date, end_date) {
+        *daily_outflow.entry(day_index(entry.date)).or_default() -= balance_change(&entry);
+    }
+
+    daily_outflow
+        .into_iter()
+        .filter(|(_, outflow)| *outflow > 0.0)
+        .fold(None, |max: Option<(u64, f64)>, (day, outflow)| match max {
+            Some((_, max_outflow)) if max_outflow >= outflow => max,
+            _ => Some((day * NANOS_PER_DAY, outflow)),
+        })
+}
+
+// Percentage change of the average daily debit of the last 7 UTC days
+// (including today) against the 7 days before. 0 when the prior week had no
+// spending, since no meaningful percentage exists.
+#[ic_cdk::query]
+fn get_spend_velocity() -> f64 {
+    let today_start = day_index(time()) * NANOS_PER_DAY;
+    let current_start = today_start.saturating_sub(6 * NANOS_PER_DAY);
+    let prior_start = current_start.saturating_sub(7 * NANOS_PER_DAY);
+
+    let current = get_average_daily_spend(current_start, today_start + NANOS_PER_DAY - 1);
+    let prior = get_average_daily_spend(prior_start, current_start.saturating_sub(1));
+    safe_div(current - prior, prior) * 100.0
+}
+
+// Median amount of one transaction type in the range, 0 when there are no
+// matching entries. All matching amounts are loaded and sorted in memory, so
+// keep the range narrow on large ledgers to stay within the instruction limit.
+#[ic_cdk::query]
+fn get_median_amount(entry_type: TransactionType, start_date: u64, end_date: u64) -> f64 {
+    let mut amounts: Vec<f64> = get_entries_by_date_range(start_date, end_date)
+        .iter()
+        .filter(|entry| entry.entry_type == entry_type)
+        .map(|entry| entry.amount)
+        .collect();
+    if amounts.is_empty() {
+        return 0.0;
+    }
+
+    amounts.sort_by(|a, b| a.total_cmp(b));
+    let middle = amounts.len() / 2;
+    if amounts.len() % 2 == 1 {
+        amounts[middle]
+    } else {
+        (amounts[middle - 1] + amounts[middle]) / 2.0
+    }
+}
+
+// Approved entries and amount per approver for approvals made in the range,
+// most active approver first. Entries created with an approver but without
+// an approval time are counted by their date, entries without an approver
+// fall into the "(unassigned)" bucket.
+#[ic_cdk::query]
+fn get_approval_summary(start_date: u64, end_date: u64) -> Vec<ApproverSummary> {
+    let mut summaries: BTreeMap<String, ApproverSummary> = BTreeMap::new();
+    PETTY_CASH_STORAGE.with(|storage| {
+        for (_, entry) in storage.borrow().iter() {
+            let approved_at = entry.approved_at.unwrap_or(entry.date);
+            if entry.scheduled || approved_at < start_date || approved_at > end_date {
+                continue;
+            }
+
+            let approver = entry
+                .approved_by
+                .unwrap_or_else(|| "(unassigned)".to_string());
+            let summary = summaries
+                .entry(approver.clone())
+                .or_insert_with(|| ApproverSummary {
+                    approver,
+                    count: 0,
+                    total_amount: 0.0,
+                });
+            summary.count += 1;
+            summary.total_amount += entry.amount;
+        }
+    });
+
+    let mut summaries: Vec<ApproverSummary> = summaries.into_values().collect();
+    summaries.sort_by_key(|summary| std::cmp::Reverse(summary.count));
+    summaries
+}
+
+// Candidate sets of entry ids whose amounts add up to target within
+// tolerance, for matching a bank figure during reconciliation. A tolerance
+// below the amount epsilon is widened to it. To stay inside
+// the instruction limit the search only looks at the MAX_SUM_SEARCH_ENTRIES
+// most recent active entries, combinations of at most MAX_SUM_COMBINATION
+// entries, stops after MAX_SUM_RESULTS matches or MAX_SUM_STEPS visited
+// combinations, so an empty result does not prove that no combination exists.
+#[ic_cdk::query]
+fn find_entries_summing_to(target: f64, tolerance: f64, max_combination: u32) -> Vec<Vec<u64>> {
+    let tolerance = tolerance.abs().max(amount_epsilon());
+    let max_combination = max_combination.min(MAX_SUM_COMBINATION) as usize;
+
+    let mut candidates: Vec<(u64, f64)> = PETTY_CASH_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, entry)| !entry.scheduled)
+            .map(|(id, entry)| (id, entry.amount))
+            .collect()
+    });
+    let recent_start = candidates.len().saturating_sub(MAX_SUM_SEARCH_ENTRIES);
+    candidates.drain(..recent_start);
+    // Ascending amounts let the search stop as soon as a sum overshoots
+    candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    let mut search = SumSearch {
+        candidates: &candidates,
+        target,
+        tolerance,
+        max_combination,
+        steps: 0,
+        chosen: Vec::new(),
+        results: Vec::new(),
+    };
+    search.run(0, 0.0);
+    search.results
+}
+
+// Totals of a calendar month. A closed month is served from the snapshot
+// taken when it was closed, so later admin edits do not change it silently.
+#[ic_cdk::query]
+fn get_monthly_report(year: u32, month: u32) -> Result<MonthlyReport, Error> {
+    validate_period(year, month)?;
+
+    let report = match get_settings().closed_periods.get(&(year, month)) {
+        Some(period) => period.snapshot.clone(),
+        None => compute_monthly_report(year, month),
+    };
+    Ok(report_for_display(report))
+}
+
+#[ic_cdk::query]
+fn get_reconciliation(id: u64) -> Result<Reconciliation, Error> {
+    RECONCILIATIONS
+        .with(|reconciliations| reconciliations.borrow().get(&id))
+        .map(reconciliation_for_display)
+        .ok_or(Error::NotFound {
+            msg: format!("Reconciliation with id={} not found", id),
+        })
+}
+
+#[ic_cdk::query]
+fn get_closed_periods() -> Vec<(u32, u32)> {
+    get_settings().closed_periods.into_keys().collect()
+}
+
+// The n entries that moved the balance the most, debits and credits alike,
+// largest absolute amount first. n is capped at MAX_PAGE_SIZE.
+#[ic_cdk::query]
+fn get_entries_by_impact(start_date: u64, end_date: u64, n: u64) -> Vec<PettyCashEntry> {
+    let mut entries = get_entries_by_date_range(start_date, end_date);
+    entries.sort_by(|a, b| b.amount.abs().total_cmp(&a.amount.abs()));
+    entries.truncate(n.min(MAX_PAGE_SIZE) as usize);
+    entries
+}
+
+// Herfindahl index of debit spending over categories: the sum of each
+// category's squared share of total debits. 1 means a single category took
+// all spending, values near 0 mean it is spread thin. 0 for an empty range.
+#[ic_cdk::query]
+fn get_concentration_index(start_date: u64, end_date: u64) -> f64 {
+    let spent = debits_by_category(start_date, end_date);
+
+    let total: f64 = spent.values().sum();
+    spent
+        .values()
+        .map(|amount| safe_div(*amount, total).powi(2))
+        .sum()
+}
+
+// Days until the balance reaches the minimum reserve at the average daily
+// debit of the trailing 30 days. None means there was no recent spending.
+#[ic_cdk::query]
+fn get_runway_days() -> Option<u32> {
+    let now = time();
+    let start_date = now.saturating_sub(29 * NANOS_PER_DAY);
+    let average_daily_spend = get_average_daily_spend(start_date, now);
+    if average_daily_spend <= 0.0 {
+        return None;
+    }
+
+    let spendable = (raw_balance() - get_settings().minimum_reserve).max(0.0);
+    Some(
+        (spendable / average_daily_spend)
+            .floor()
+            .min(u32::MAX as f64) as u32,
+    )
+}
+
+// Update Methods
+#[ic_cdk::update]
+fn add_entry(payload: EntryPayload) -> Result<PettyCashEntry, Error> {
+    ensure_initialized()?;
+
+    // Validate amount
+    if payload.amount <= 0.0 {
+        return Err(Error::InvalidAmount {
+            msg: "Amount must be greater than 0".to_string(),
+        });
+    }
+    let attachments = payload.attachments.clone().unwrap_or_default();
+    validate_entry_fields(&payload, &attachments)?;
+    ensure_description_policy(payload.entry_type, payload.amount, &payload.description)?;
+
+    let now = time();
+    let date = payload.date.unwrap_or(now);
+    let scheduled = date > now;
+    ensure_period_open(date)?;
+    ensure_category_capacity(&payload.category, date, None)?;
+
+    // Check if sufficient funds for debit transactions, scheduled entries
+    // are checked again when they are activated
+    if !scheduled && matches!(payload.entry_type, TransactionType::Debit) {
+        let current_balance = raw_balance();
+        let reserved = reserved_balance();
+        if current_balance - payload.amount < reserved {
+            return Err(Error::InsufficientFunds {
+                msg: format!(
+                    "Insufficient funds. Current balance: {}, Required: {}, Reserved: {}",
+                    current_balance, payload.amount, reserved
+                ),
+            });
+        }
+    }
+
+    let entry = PettyCashEntry {
+        id: next_entry_id(),
+        date,
+        description: payload.description,
+        amount: payload.amount,
+        entry_type: payload.entry_type,
+        category: payload.category,
+        receipt_url: payload.receipt_url,
+        // New entries start pending, see approve_entry
+        approved_at: None,
+        approved_by: None,
+        approval_signature: None,
+        created_at: now,
+        updated_at: None,
+        scheduled,
+        attachments,
+        locked: false,
+        reconciliation_id: None,
+        billable_to: normalize_client(payload.billable_to),
+    };
+
+    // Update balance
+    if !scheduled {
+        apply_balance_change(balance_change(&entry));
+    }
+
+    do_insert(&entry);
+    log_audit(
+        AuditAction::Create,
+        Some(entry.id),
+        format!("{:?} of {}", entry.entry_type, entry.amount),
+    );
+    Ok(entry)
+}
+
+#[ic_cdk::update]
+fn update_entry(id: u64, payload: EntryPayload) -> Result<PettyCashEntry, Error> {
+    ensure_initialized()?;
+
+    match PETTY_CASH_STORAGE.with(|service| service.borrow().get(&id)) {
+        Some(mut entry) => {
+            ensure_editable(&entry)?;
+            let attachments = payload
+                .attachments
+                .clone()
+                .unwrap_or_else(|| entry.attachments.clone());
+            validate_entry_fields(&payload, &attachments)?;
+            ensure_description_policy(payload.entry_type, payload.amount, &payload.description)?;
+            let (old_entry_type, old_amount) = (entry.entry_type, entry.amount);
+
+            let date = payload.date.unwrap_or(entry.date);
+            let scheduled = date > time();
+            ensure_period_open(date)?;
+            // Only moving into another category or month uses up capacity
+            let moved = normalize_category(&payload.category)
+                != normalize_category(&entry.category)
+                || month_of(date) != month_of(entry.date);
+            if moved {
+                ensure_category_capacity(&payload.category, date, Some(entry.id))?;
+            }
+
+            // Update balance
+            let balance_delta =
+                update_balance_delta(&entry, payload.entry_type, payload.amount, scheduled);
+            let reserved = reserved_balance();
+            BALANCE.with(|balance| {
+                let current_balance = *balance.borrow().get();
+                let new_balance = current_balance + balance_delta;
+
+                // Only changes that lower the balance are held to the reserve
+                if balance_delta < 0.0 && new_balance < reserved {
+                    return Err(Error::InsufficientFunds {
+                        msg: format!(
+                            "Update would take the balance below the reserved amount of {}",
+                            reserved
+                        ),
+                    });
+                }
+
+                balance
+                    .borrow_mut()
+                    .set(new_balance)
+                    .expect("Cannot update balance");
+                Ok(())
+            })?;
+
+            // A Debit <-> Credit flip swings the balance by twice the amount,
+            // so the entry goes back to pending (unapproved) when configured
+            if entry.entry_type != payload.entry_type && get_settings().reapprove_on_type_flip {
+                entry.approved_by = None;
+                entry.approved_at = None;
+                entry.approval_signature = None;
+            }
+
+            // Update entry fields
+            entry.date = date;
+            entry.description = payload.description;
+            entry.amount = payload.amount;
+            entry.entry_type = payload.entry_type;
             entry.category = payload.category;
             entry.receipt_url = payload.receipt_url;
-            entry.approved_by = payload.approved_by;
             entry.updated_at = Some(time());
+            entry.scheduled = scheduled;
+            entry.attachments = attachments;
+            entry.billable_to = normalize_client(payload.billable_to);
 
             do_insert(&entry);
+            insert_audit_record(
+                AuditAction::Update,
+                Some(entry.id),
+                format!(
+                    "{:?} of {} -> {:?} of {}",
+                    old_entry_type, old_amount, entry.entry_type, entry.amount
+                ),
+                // Any change to the stored amount is history, however small
+                (old_amount != entry.amount).then_some((old_amount, entry.amount)),
+            );
             Ok(entry)
         }
         None => Err(Error::NotFound {
@@ -232,22 +1415,23 @@ fn update_entry(id: u64, payload: EntryPayload) -> Result<PettyCashEntry, Error>
 
 #[ic_cdk::update]
 fn delete_entry(id: u64) -> Result<PettyCashEntry, Error> {
+    ensure_initialized()?;
+    if let Some(entry) = _get_entry(&id) {
+        ensure_editable(&entry)?;
+    }
+
     match PETTY_CASH_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
         Some(entry) => {
             // Update balance
-            let balance_change = match entry.entry_type {
-                TransactionType::Credit => -entry.amount,
-                TransactionType::Debit => entry.amount,
-            };
-
-            BALANCE.with(|balance| {
-                let current_balance = *balance.borrow().get();
-                balance
-                    .borrow_mut()
-                    .set(current_balance + balance_change)
-                    .expect("Cannot update balance");
-            });
+            if !entry.scheduled {
+                apply_balance_change(-balance_change(&entry));
+            }
 
+            log_audit(
+                AuditAction::Delete,
+                Some(entry.id),
+                format!("{:?} of {}", entry.entry_type, entry.amount),
+            );
             Ok(entry)
         }
         None => Err(Error::NotFound {
@@ -256,16 +1440,2293 @@ fn delete_entry(id: u64) -> Result<PettyCashEntry, Error> {
     }
 }
 
-// Helper functions
-fn do_insert(entry: &PettyCashEntry) {
-    PETTY_CASH_STORAGE.with(|service| {
-        service.borrow_mut().insert(entry.id, entry.clone())
-    });
+// Fixes a mis-keyed amount without touching any other field. The old and new
+// amounts and the reason are kept in the audit log.
+#[ic_cdk::update]
+fn correct_amount(id: u64, new_amount: f64, reason: String) -> Result<PettyCashEntry, Error> {
+    ensure_initialized()?;
+
+    if new_amount <= 0.0 {
+        return Err(Error::InvalidAmount {
+            msg: "Amount must be greater than 0".to_string(),
+        });
+    }
+    if reason.trim().is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "A reason is required to correct an amount".to_string(),
+        });
+    }
+
+    let mut entry = _get_entry(&id).ok_or(Error::NotFound {
+        msg: format!("Entry with id={} not found", id),
+    })?;
+    ensure_editable(&entry)?;
+    ensure_description_policy(entry.entry_type, new_amount, &entry.description)?;
+    let old_amount = entry.amount;
+
+    if !entry.scheduled {
+        let old_change = balance_change(&entry);
+        entry.amount = new_amount;
+        let delta = balance_change(&entry) - old_change;
+        let new_balance = raw_balance() + delta;
+        let reserved = reserved_balance();
+        if delta < 0.0 && new_balance < reserved {
+            return Err(Error::InsufficientFunds {
+                msg: format!(
+                    "Correction would take the balance below the reserved amount of {}",
+                    reserved
+                ),
+            });
+        }
+        BALANCE.with(|balance| {
+            balance
+                .borrow_mut()
+                .set(new_balance)
+                .expect("Cannot update balance")
+        });
+    }
+
+    entry.amount = new_amount;
+    entry.updated_at = Some(time());
+    do_insert(&entry);
+    insert_audit_record(
+        AuditAction::CorrectAmount,
+        Some(entry.id),
+        format!("Amount {} -> {}: {}", old_amount, new_amount, reason.trim()),
+        Some((old_amount, new_amount)),
+    );
+    Ok(entry)
 }
 
-fn _get_entry(id: &u64) -> Option<PettyCashEntry> {
-    PETTY_CASH_STORAGE.with(|service| service.borrow().get(id))
+// Freezes an entry against edits, e.g. while it is being reconciled.
+// The admin or an approver of the entry's category can lock it, in a closed
+// period only the admin can.
+#[ic_cdk::update]
+fn lock_entry(id: u64) -> Result<PettyCashEntry, Error> {
+    ensure_initialized()?;
+
+    let mut entry = _get_entry(&id).ok_or(Error::NotFound {
+        msg: format!("Entry with id={} not found", id),
+    })?;
+    ensure_approver(&entry.category)?;
+    ensure_period_open(entry.date)?;
+
+    entry.locked = true;
+    do_insert(&entry);
+    log_audit(AuditAction::Lock, Some(entry.id), "Entry locked");
+    Ok(entry)
+}
+
+#[ic_cdk::update]
+fn unlock_entry(id: u64) -> Result<PettyCashEntry, Error> {
+    ensure_admin()?;
+
+    let mut entry = _get_entry(&id).ok_or(Error::NotFound {
+        msg: format!("Entry with id={} not found", id),
+    })?;
+
+    entry.locked = false;
+    do_insert(&entry);
+    log_audit(AuditAction::Unlock, Some(entry.id), "Entry unlocked");
+    Ok(entry)
+}
+
+// Moves entries of source_category to the target of the first rule whose
+// keyword appears in their description, ignoring case. Unmatched and locked
+// entries, and entries that would outgrow PettyCashEntry::MAX_SIZE with the
+// longer category, stay where they are and balances are not affected. Returns the
+// moved count per target category.
+#[ic_cdk::update]
+fn reclassify_by_keyword(
+    source_category: String,
+    rules: Vec<(String, String)>,
+) -> Result<Vec<(String, u64)>, Error> {
+    ensure_admin()?;
+
+    if rules
+        .iter()
+        .any(|(keyword, target)| keyword.trim().is_empty() || target.trim().is_empty())
+    {
+        return Err(Error::InvalidInput {
+            msg: "Rules need a non-empty keyword and target category".to_string(),
+        });
+    }
+    if rules
+        .iter()
+        .any(|(_, target)| target.trim().len() > MAX_CATEGORY_LEN)
+    {
+        return Err(Error::InvalidInput {
+            msg: format!("Categories can be at most {} bytes", MAX_CATEGORY_LEN),
+        });
+    }
+
+    let source_category = normalize_category(&source_category);
+    let rules: Vec<(String, String)> = rules
+        .into_iter()
+        .map(|(keyword, target)| (keyword.trim().to_lowercase(), target.trim().to_string()))
+        .collect();
+
+    let candidates: Vec<PettyCashEntry> = PETTY_CASH_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, entry)| !entry.locked)
+            .filter(|(_, entry)| normalize_category(&entry.category) == source_category)
+            .map(|(_, entry)| entry)
+            .collect()
+    });
+
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    let now = time();
+    for mut entry in candidates {
+        let description = entry.description.to_lowercase();
+        let matched = rules
+            .iter()
+            .find(|(keyword, _)| description.contains(keyword.as_str()));
+        if let Some((_, target)) = matched {
+            let previous = std::mem::replace(&mut entry.category, target.clone());
+            if !entry_fits(&entry) {
+                continue;
+            }
+            entry.updated_at = Some(now);
+            do_insert(&entry);
+            log_audit(
+                AuditAction::Update,
+                Some(entry.id),
+                format!("Reclassified from {} to {}", previous, target),
+            );
+            *counts.entry(normalize_category(target)).or_default() += 1;
+        }
+    }
+
+    Ok(counts.into_iter().collect())
+}
+
+// Approves an entry as the caller. When its category has a designated
+// approver only that principal may approve, otherwise the admin or any
+// designated category approver can. The optional signature is a 32 or 64
+// byte hex string that off-chain systems can use to cross-verify the approval.
+// Locked entries cannot be approved, entries in closed periods only by the admin.
+#[ic_cdk::update]
+fn approve_entry(id: u64, signature: Option<String>) -> Result<PettyCashEntry, Error> {
+    ensure_initialized()?;
+
+    let signature = signature.map(|signature| signature.trim().to_lowercase());
+    if let Some(signature) = &signature {
+        if !is_valid_signature(signature) {
+            return Err(Error::InvalidInput {
+                msg: "Approval signature must be 64 or 128 hex characters".to_string(),
+            });
+        }
+    }
+
+    let mut entry = _get_entry(&id).ok_or(Error::NotFound {
+        msg: format!("Entry with id={} not found", id),
+    })?;
+    ensure_approver(&entry.category)?;
+    ensure_editable(&entry)?;
+
+    if entry.approved_by.is_some() {
+        return Err(Error::InvalidInput {
+            msg: format!("Entry with id={} is already approved", id),
+        });
+    }
+
+    let approver = caller().to_text();
+    let now = time();
+    entry.approved_by = Some(approver.clone());
+    entry.approved_at = Some(now);
+    entry.approval_signature = signature;
+    entry.updated_at = Some(now);
+    do_insert(&entry);
+    log_audit(
+        AuditAction::Approve,
+        Some(entry.id),
+        format!("Approved by {}", approver),
+    );
+    Ok(entry)
+}
+
+// Records approver as the approver of unapproved entries after the fact,
+// e.g. following a retroactive approval meeting. The approver must be allowed
+// to approve each entry's category. Amounts and balances are untouched.
+// Ids that don't exist are skipped and listed in the audit trail, entries
+// that are already approved, locked or in a closed period are left alone.
+// Returns the number updated.
+#[ic_cdk::update]
+fn bulk_assign_approver(ids: Vec<u64>, approver: String) -> Result<u64, Error> {
+    ensure_admin()?;
+
+    let principal = Principal::from_text(approver.trim()).map_err(|_| Error::InvalidInput {
+        msg: format!("Approver {} is not a valid principal", approver),
+    })?;
+    let settings = get_settings();
+
+    let mut entries = Vec::new();
+    let mut missing = Vec::new();
+    for id in ids.into_iter().collect::<BTreeSet<u64>>() {
+        match _get_entry(&id) {
+            Some(entry) => {
+                // The admin may edit closed periods, but a bulk run should not
+                let (year, month) = month_of(entry.date);
+                let closed = settings.closed_periods.contains_key(&(year as u32, month));
+                if entry.approved_by.is_none() && !entry.locked && !closed {
+                    entries.push(entry);
+                }
+            }
+            None => missing.push(id),
+        }
+    }
+
+    if let Some(entry) = entries
+        .iter()
+        .find(|entry| !is_approver_for(&settings, principal, &entry.category))
+    {
+        return Err(Error::Unauthorized {
+            msg: format!(
+                "{} is not an approver for category {} of entry id={}",
+                principal, entry.category, entry.id
+            ),
+        });
+    }
+
+    let approver = principal.to_text();
+    let now = time();
+    for entry in &mut entries {
+        entry.approved_by = Some(approver.clone());
+        entry.approved_at = Some(now);
+        entry.updated_at = Some(now);
+        do_insert(entry);
+        log_audit(
+            AuditAction::Approve,
+            Some(entry.id),
+            format!("Approver {} assigned in bulk", approver),
+        );
+    }
+    if !missing.is_empty() {
+        log_audit(
+            AuditAction::Approve,
+            None,
+            format!("Bulk approver assignment skipped missing ids {:?}", missing),
+        );
+    }
+
+    Ok(entries.len() as u64)
+}
+
+// Activates scheduled entries whose date has arrived, applying them to the
+// balance in date order. A due debit that the balance cannot cover stays
+// scheduled and is retried on the next run. Returns the activated entries.
+#[ic_cdk::update]
+fn run_activate_scheduled() -> Result<Vec<PettyCashEntry>, Error> {
+    ensure_initialized()?;
+
+    let now = time();
+    let mut due: Vec<PettyCashEntry> = PETTY_CASH_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(_, entry)| entry.scheduled && entry.date <= now)
+            .map(|(_, entry)| entry)
+            .collect()
+    });
+    due.sort_by_key(|entry| (entry.date, entry.id));
+
+    let reserved = reserved_balance();
+    let mut activated = Vec::new();
+    for mut entry in due {
+        let change = balance_change(&entry);
+        if change < 0.0 && raw_balance() + change < reserved {
+            continue;
+        }
+
+        apply_balance_change(change);
+        entry.scheduled = false;
+        entry.updated_at = Some(now);
+        do_insert(&entry);
+        log_audit(
+            AuditAction::Activate,
+            Some(entry.id),
+            "Scheduled entry activated",
+        );
+        activated.push(entry);
+    }
+    Ok(activated)
+}
+
+// Lets a controller claim a canister that was installed before the admin
+// existed. Once an admin is set it can only be handed over with
+// transfer_admin and accept_admin.
+#[ic_cdk::update]
+fn set_admin(new_admin: Principal) -> Result<(), Error> {
+    if get_settings().admin.is_some() {
+        return Err(Error::Unauthorized {
+            msg: "The admin is already set, use transfer_admin to hand it over".to_string(),
+        });
+    }
+    if !is_controller(&caller()) {
+        return Err(Error::Unauthorized {
+            msg: "Only a controller can set the initial admin".to_string(),
+        });
+    }
+
+    if new_admin == Principal::anonymous() {
+        return Err(Error::Unauthorized {
+            msg: "The anonymous principal cannot be the admin".to_string(),
+        });
+    }
+
+    update_settings(|settings| {
+        settings.admin = Some(new_admin);
+        settings.pending_admin = None;
+        settings.initialized = true;
+    });
+    log_audit(
+        AuditAction::AdminChange,
+        None,
+        format!("Admin set to {}", new_admin),
+    );
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn set_category_budget(category: String, monthly_amount: f64) -> Result<(), Error> {
+    ensure_admin()?;
+
+    if monthly_amount < 0.0 || !monthly_amount.is_finite() {
+        return Err(Error::InvalidAmount {
+            msg: "Budget must be a non-negative amount".to_string(),
+        });
+    }
+
+    update_settings(|settings| {
+        settings
+            .budgets
+            .insert(normalize_category(&category), monthly_amount);
+    });
+    log_audit(
+        AuditAction::Configure,
+        None,
+        format!("Budget for {} set to {}", category, monthly_amount),
+    );
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn set_category_approver(category: String, approver: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+
+    if approver == Principal::anonymous() {
+        return Err(Error::Unauthorized {
+            msg: "The anonymous principal cannot be an approver".to_string(),
+        });
+    }
+
+    update_settings(|settings| {
+        settings
+            .category_approvers
+            .insert(normalize_category(&category), approver);
+    });
+    log_audit(
+        AuditAction::Configure,
+        None,
+        format!("Approver for {} set to {}", category, approver),
+    );
+    Ok(())
+}
+
+// Places a category under a parent for rolled up summaries, None detaches it.
+// A parent may not be the category itself or one of its descendants.
+#[ic_cdk::update]
+fn set_category_parent(category: String, parent: Option<String>) -> Result<(), Error> {
+    ensure_admin()?;
+
+    let category = normalize_category(&category);
+    let parent = parent.map(|parent| normalize_category(&parent));
+    if category.is_empty() || parent.as_deref() == Some("") {
+        return Err(Error::InvalidInput {
+            msg: "Category names cannot be empty".to_string(),
+        });
+    }
+
+    let details = match &parent {
+        Some(parent) => format!("Parent of {} set to {}", category, parent),
+        None => format!("Parent of {} removed", category),
+    };
+    let mut parents = get_settings().category_parents;
+    match parent {
+        Some(parent) => parents.insert(category.clone(), parent),
+        None => parents.remove(&category),
+    };
+    if has_category_cycle(&parents) {
+        return Err(Error::InvalidInput {
+            msg: format!("The parent of {} would create a cycle", category),
+        });
+    }
+    update_settings(|settings| settings.category_parents = parents);
+    log_audit(AuditAction::Configure, None, details);
+    Ok(())
+}
+
+// Caps how many entries a category may receive per calendar month, passing
+// 0 removes the cap. Categories have no cap by default.
+#[ic_cdk::update]
+fn set_category_entry_limit(category: String, monthly_limit: u64) -> Result<(), Error> {
+    ensure_admin()?;
+
+    let category = normalize_category(&category);
+    update_settings(|settings| {
+        if monthly_limit == 0 {
+            settings.category_entry_limits.remove(&category);
+        } else {
+            settings
+                .category_entry_limits
+                .insert(category.clone(), monthly_limit);
+        }
+    });
+    log_audit(
+        AuditAction::Configure,
+        None,
+        format!(
+            "Monthly entry limit for {} set to {}",
+            category, monthly_limit
+        ),
+    );
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn set_display_precision(decimals: u32) -> Result<(), Error> {
+    ensure_admin()?;
+
+    if decimals > MAX_DISPLAY_PRECISION {
+        return Err(Error::InvalidInput {
+            msg: format!("Display precision can be at most {}", MAX_DISPLAY_PRECISION),
+        });
+    }
+
+    update_settings(|settings| settings.display_precision = Some(decimals));
+    log_audit(
+        AuditAction::Configure,
+        None,
+        format!("Display precision set to {}", decimals),
+    );
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn set_reapprove_on_type_flip(enabled: bool) -> Result<(), Error> {
+    ensure_admin()?;
+    update_settings(|settings| settings.reapprove_on_type_flip = enabled);
+    log_audit(
+        AuditAction::Configure,
+        None,
+        format!("Re-approval on type flip set to {}", enabled),
+    );
+    Ok(())
+}
+
+// Starts the ledger at the counted cash of an existing fund. Only allowed
+// while there are no entries, so history is never rewritten. The amount is
+// recorded as a locked Credit entry dated as_of_date.
+#[ic_cdk::update]
+fn set_opening_balance(amount: f64, as_of_date: u64) -> Result<PettyCashEntry, Error> {
+    ensure_admin()?;
+
+    if amount <= 0.0 || !amount.is_finite() {
+        return Err(Error::InvalidAmount {
+            msg: "Amount must be greater than 0".to_string(),
+        });
+    }
+    let now = time();
+    if as_of_date > now {
+        return Err(Error::InvalidInput {
+            msg: "The opening balance date cannot be in the future".to_string(),
+        });
+    }
+    if PETTY_CASH_STORAGE.with(|storage| !storage.borrow().is_empty()) {
+        return Err(Error::InvalidInput {
+            msg: "The opening balance can only be set before any entry exists".to_string(),
+        });
+    }
+
+    let entry = PettyCashEntry {
+        id: next_entry_id(),
+        date: as_of_date,
+        description: "Opening balance".to_string(),
+        amount,
+        entry_type: TransactionType::Credit,
+        category: OPENING_BALANCE_CATEGORY.to_string(),
+        created_at: now,
+        locked: true,
+        ..Default::default()
+    };
+
+    BALANCE.with(|balance| {
+        balance
+            .borrow_mut()
+            .set(amount)
+            .expect("Cannot update balance")
+    });
+    do_insert(&entry);
+    log_audit(
+        AuditAction::Create,
+        Some(entry.id),
+        format!("Opening balance of {}", amount),
+    );
+    Ok(entry)
+}
+
+// Closes a month and snapshots its report. Entries dated in a closed month
+// can then only be added, changed or deleted by the admin.
+#[ic_cdk::update]
+fn close_period(year: u32, month: u32) -> Result<MonthlyReport, Error> {
+    ensure_admin()?;
+    validate_period(year, month)?;
+
+    if get_settings().closed_periods.contains_key(&(year, month)) {
+        return Err(Error::InvalidInput {
+            msg: format!("Period {}-{:02} is already closed", year, month),
+        });
+    }
+
+    let snapshot = store_period_snapshot(year, month);
+    log_audit(
+        AuditAction::ClosePeriod,
+        None,
+        format!(
+            "Closed {}-{:02} with balance {}",
+            year, month, snapshot.closing_balance
+        ),
+    );
+    Ok(report_for_display(snapshot))
+}
+
+// Retakes the snapshot of a closed month after the admin legitimately
+// adjusted one of its entries
+#[ic_cdk::update]
+fn resnapshot_period(year: u32, month: u32) -> Result<MonthlyReport, Error> {
+    ensure_admin()?;
+    validate_period(year, month)?;
+
+    if !get_settings().closed_periods.contains_key(&(year, month)) {
+        return Err(Error::NotFound {
+            msg: format!("Period {}-{:02} is not closed", year, month),
+        });
+    }
+
+    let snapshot = store_period_snapshot(year, month);
+    log_audit(
+        AuditAction::ClosePeriod,
+        None,
+        format!(
+            "Re-snapshot {}-{:02} with balance {}",
+            year, month, snapshot.closing_balance
+        ),
+    );
+    Ok(report_for_display(snapshot))
+}
+
+// Reopens a closed month for corrections. Its snapshot is dropped, so reports
+// are recomputed live until the month is closed again.
+#[ic_cdk::update]
+fn reopen_period(year: u32, month: u32, reason: String) -> Result<(), Error> {
+    ensure_admin()?;
+    validate_period(year, month)?;
+
+    if reason.trim().is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "A reason is required to reopen a period".to_string(),
+        });
+    }
+    if !get_settings().closed_periods.contains_key(&(year, month)) {
+        return Err(Error::NotFound {
+            msg: format!("Period {}-{:02} is not closed", year, month),
+        });
+    }
+
+    update_settings(|settings| {
+        settings.closed_periods.remove(&(year, month));
+    });
+    log_audit(
+        AuditAction::ReopenPeriod,
+        None,
+        format!("Reopened {}-{:02}: {}", year, month, reason.trim()),
+    );
+    Ok(())
+}
+
+// Records a physical cash count against the current balance. Any difference
+// is corrected separately with create_reconciliation_adjustment.
+#[ic_cdk::update]
+fn reconcile(counted_amount: f64, note: String) -> Result<Reconciliation, Error> {
+    ensure_admin()?;
+
+    if counted_amount < 0.0 || !counted_amount.is_finite() {
+        return Err(Error::InvalidAmount {
+            msg: "Counted amount must be a non-negative amount".to_string(),
+        });
+    }
+
+    let id = RECONCILIATION_ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment reconciliation counter");
+    let expected_balance = raw_balance();
+    let reconciliation = Reconciliation {
+        id,
+        counted_amount,
+        expected_balance,
+        difference: counted_amount - expected_balance,
+        note: truncate_bytes(&note, MAX_AUDIT_DETAILS_LEN),
+        reconciled_at: time(),
+        reconciled_by: caller(),
+    };
+
+    RECONCILIATIONS.with(|reconciliations| {
+        reconciliations
+            .borrow_mut()
+            .insert(id, reconciliation.clone())
+    });
+    log_audit(
+        AuditAction::Reconcile,
+        None,
+        format!(
+            "Reconciliation {}: counted {}, expected {}",
+            id, counted_amount, expected_balance
+        ),
+    );
+    Ok(reconciliation_for_display(reconciliation))
+}
+
+// Books a correction for a reconciliation. A negative amount records a
+// shortage as a Debit, a positive amount a surplus as a Credit. The entry is
+// linked to the reconciliation and locked. Shortages may dip into the reserve
+// because they record cash that is already gone, but never below zero.
+#[ic_cdk::update]
+fn create_reconciliation_adjustment(
+    reconciliation_id: u64,
+    amount: f64,
+    reason: String,
+) -> Result<PettyCashEntry, Error> {
+    ensure_admin()?;
+
+    get_reconciliation(reconciliation_id)?;
+    if amount_equals(amount, 0.0) || !amount.is_finite() {
+        return Err(Error::InvalidAmount {
+            msg: "Adjustment amount must be non-zero".to_string(),
+        });
+    }
+    if reason.trim().is_empty() {
+        return Err(Error::InvalidInput {
+            msg: "A reason is required for an adjustment".to_string(),
+        });
+    }
+    // The reason becomes the entry description
+    if reason.trim().len() > MAX_DESCRIPTION_LEN {
+        return Err(Error::InvalidInput {
+            msg: format!("Reason can be at most {} bytes", MAX_DESCRIPTION_LEN),
+        });
+    }
+    if raw_balance() + amount < 0.0 {
+        return Err(Error::InsufficientFunds {
+            msg: "Adjustment would result in negative balance".to_string(),
+        });
+    }
+
+    let now = time();
+    let entry = PettyCashEntry {
+        id: next_entry_id(),
+        date: now,
+        description: reason.trim().to_string(),
+        amount: amount.abs(),
+        entry_type: if amount < 0.0 {
+            TransactionType::Debit
+        } else {
+            TransactionType::Credit
+        },
+        category: ADJUSTMENT_CATEGORY.to_string(),
+        created_at: now,
+        locked: true,
+        reconciliation_id: Some(reconciliation_id),
+        ..Default::default()
+    };
+
+    apply_balance_change(balance_change(&entry));
+    do_insert(&entry);
+    log_audit(
+        AuditAction::Adjust,
+        Some(entry.id),
+        format!(
+            "Adjustment of {} for reconciliation {}: {}",
+            amount,
+            reconciliation_id,
+            reason.trim()
+        ),
+    );
+    Ok(entry)
+}
+
+// Earmarks part of the available balance for a commitment
+#[ic_cdk::update]
+fn allocate(amount: f64) -> Result<f64, Error> {
+    ensure_admin()?;
+
+    if amount <= 0.0 || !amount.is_finite() {
+        return Err(Error::InvalidAmount {
+            msg: "Amount must be greater than 0".to_string(),
+        });
+    }
+    // The minimum reserve cannot be earmarked
+    let available = raw_available() - get_settings().minimum_reserve;
+    if amount > available {
+        return Err(Error::InsufficientFunds {
+            msg: format!(
+                "Cannot allocate {}, available balance above the reserve: {}",
+                amount, available
+            ),
+        });
+    }
+
+    let allocated = raw_allocated() + amount;
+    set_allocated(allocated);
+    log_audit(
+        AuditAction::Configure,
+        None,
+        format!("Allocated {}, total allocated {}", amount, allocated),
+    );
+    Ok(round_for_display(allocated))
+}
+
+#[ic_cdk::update]
+fn release(amount: f64) -> Result<f64, Error> {
+    ensure_admin()?;
+
+    if amount <= 0.0 || !amount.is_finite() {
+        return Err(Error::InvalidAmount {
+            msg: "Amount must be greater than 0".to_string(),
+        });
+    }
+    let allocated = raw_allocated();
+    if amount > allocated {
+        return Err(Error::InvalidAmount {
+            msg: format!(
+                "Cannot release {}, allocated balance: {}",
+                amount, allocated
+            ),
+        });
+    }
+
+    let allocated = allocated - amount;
+    set_allocated(allocated);
+    log_audit(
+        AuditAction::Configure,
+        None,
+        format!("Released {}, total allocated {}", amount, allocated),
+    );
+    Ok(round_for_display(allocated))
+}
+
+#[ic_cdk::update]
+fn set_enforce_available_balance(enabled: bool) -> Result<(), Error> {
+    ensure_admin()?;
+    update_settings(|settings| settings.enforce_available_balance = enabled);
+    log_audit(
+        AuditAction::Configure,
+        None,
+        format!("Available balance enforcement set to {}", enabled),
+    );
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn set_minimum_reserve(amount: f64) -> Result<(), Error> {
+    ensure_admin()?;
+
+    if amount < 0.0 || !amount.is_finite() {
+        return Err(Error::InvalidAmount {
+            msg: "Minimum reserve must be a non-negative amount".to_string(),
+        });
+    }
+
+    update_settings(|settings| settings.minimum_reserve = amount);
+    log_audit(
+        AuditAction::Configure,
+        None,
+        format!("Minimum reserve set to {}", amount),
+    );
+    Ok(())
+}
+
+// Requires debits above min_amount to have a description of at least
+// min_length characters. None turns the policy off. Existing entries are not
+// checked, see get_under_documented_entries.
+#[ic_cdk::update]
+fn set_description_policy(policy: Option<DescriptionPolicy>) -> Result<(), Error> {
+    ensure_admin()?;
+
+    if let Some(policy) = &policy {
+        if policy.min_amount < 0.0 || !policy.min_amount.is_finite() {
+            return Err(Error::InvalidAmount {
+                msg: "Policy threshold must be a non-negative amount".to_string(),
+            });
+        }
+    }
+
+    update_settings(|settings| settings.description_policy = policy);
+    log_audit(
+        AuditAction::Configure,
+        None,
+        match policy {
+            Some(policy) => format!(
+                "Debits above {} need descriptions of {} characters",
+                policy.min_amount, policy.min_length
+            ),
+            None => "Description policy removed".to_string(),
+        },
+    );
+    Ok(())
+}
+
+// First step of an admin handoff. The new admin only takes over after it
+// calls accept_admin itself, so a mistyped principal cannot lock the
+// canister. A later proposal replaces an earlier one.
+#[ic_cdk::update]
+fn transfer_admin(new_admin: Principal) -> Result<(), Error> {
+    ensure_admin()?;
+
+    if new_admin == Principal::anonymous() {
+        return Err(Error::Unauthorized {
+            msg: "The anonymous principal cannot be the admin".to_string(),
+        });
+    }
+
+    update_settings(|settings| settings.pending_admin = Some(new_admin));
+    log_audit(
+        AuditAction::AdminChange,
+        None,
+        format!("Admin transfer to {} proposed", new_admin),
+    );
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn accept_admin() -> Result<(), Error> {
+    let caller = caller();
+    let settings = get_settings();
+    if settings.pending_admin != Some(caller) {
+        return Err(Error::Unauthorized {
+            msg: "No admin transfer is pending for the caller".to_string(),
+        });
+    }
+
+    update_settings(|settings| {
+        settings.admin = Some(caller);
+        settings.pending_admin = None;
+    });
+    log_audit(
+        AuditAction::AdminChange,
+        None,
+        format!(
+            "Admin transferred from {} to {}",
+            settings
+                .admin
+                .map_or_else(|| "(none)".to_string(), |admin| admin.to_string()),
+            caller
+        ),
+    );
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn cancel_admin_transfer() -> Result<(), Error> {
+    ensure_admin()?;
+    update_settings(|settings| settings.pending_admin = None);
+    log_audit(AuditAction::AdminChange, None, "Admin transfer cancelled");
+    Ok(())
+}
+
+// First step of a destructive operation. The returned token is bound to the
+// calling admin, is valid for DESTRUCTIVE_TOKEN_TTL and can be used once.
+// Requesting a new token invalidates the previous one.
+#[ic_cdk::update]
+async fn request_destructive_token() -> Result<String, Error> {
+    ensure_admin()?;
+
+    let (bytes,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(_, msg)| Error::InvalidInput {
+            msg: format!("Cannot generate a token: {}", msg),
+        })?;
+    let token: String = bytes
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let destructive_token = DestructiveToken {
+        token: token.clone(),
+        owner: caller(),
+        expires_at: time() + DESTRUCTIVE_TOKEN_TTL,
+    };
+    update_settings(|settings| settings.destructive_token = Some(destructive_token));
+    Ok(token)
+}
+
+// Removes every entry and resets the balance and allocations. The id counter
+// is kept so removed ids are never reused. Returns the number of removed entries.
+#[ic_cdk::update]
+fn clear_all_entries(token: String) -> Result<u64, Error> {
+    ensure_admin()?;
+    consume_destructive_token(&token)?;
+
+    let ids: Vec<u64> =
+        PETTY_CASH_STORAGE.with(|storage| storage.borrow().iter().map(|(id, _)| id).collect());
+    PETTY_CASH_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for id in &ids {
+            storage.remove(id);
+        }
+    });
+    BALANCE.with(|balance| {
+        balance
+            .borrow_mut()
+            .set(0.0)
+            .expect("Cannot update balance")
+    });
+    set_allocated(0.0);
+
+    log_audit(
+        AuditAction::Purge,
+        None,
+        format!("Cleared {} entries", ids.len()),
+    );
+    Ok(ids.len() as u64)
+}
+
+// Restores a blob from export_snapshot, replacing entries, reconciliations,
+// balances, counters and settings. The current admin stays in charge. A
+// ledger that already has entries is only overwritten when force is set and
+// a token from request_destructive_token is passed. Returns the number of
+// restored entries.
+#[ic_cdk::update]
+fn import_snapshot(bytes: Vec<u8>, force: bool, token: Option<String>) -> Result<u64, Error> {
+    ensure_admin()?;
+
+    let snapshot = Decode!(&bytes, LedgerSnapshot).map_err(|_| Error::InvalidInput {
+        msg: "Snapshot could not be decoded".to_string(),
+    })?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Snapshot version {} is not supported, expected {}",
+                snapshot.version, SNAPSHOT_VERSION
+            ),
+        });
+    }
+    if has_category_cycle(&snapshot.settings.category_parents) {
+        return Err(Error::InvalidInput {
+            msg: "Snapshot category parents contain a cycle".to_string(),
+        });
+    }
+    let is_empty = PETTY_CASH_STORAGE.with(|storage| storage.borrow().is_empty());
+    if !is_empty {
+        if !force {
+            return Err(Error::InvalidInput {
+                msg: "The ledger already has entries, import with force to overwrite".to_string(),
+            });
+        }
+        consume_destructive_token(token.as_deref().unwrap_or(""))?;
+    }
+
+    let ids: Vec<u64> =
+        PETTY_CASH_STORAGE.with(|storage| storage.borrow().iter().map(|(id, _)| id).collect());
+    PETTY_CASH_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for id in &ids {
+            storage.remove(id);
+        }
+        for entry in &snapshot.entries {
+            storage.insert(entry.id, entry.clone());
+        }
+    });
+    let reconciliation_ids: Vec<u64> = RECONCILIATIONS
+        .with(|reconciliations| reconciliations.borrow().iter().map(|(id, _)| id).collect());
+    RECONCILIATIONS.with(|reconciliations| {
+        let mut reconciliations = reconciliations.borrow_mut();
+        for id in &reconciliation_ids {
+            reconciliations.remove(id);
+        }
+        for reconciliation in &snapshot.reconciliations {
+            reconciliations.insert(reconciliation.id, reconciliation.clone());
+        }
+    });
+
+    // Never hand out an id that a restored record already uses
+    let next_entry_id = snapshot
+        .entries
+        .iter()
+        .map(|entry| entry.id + 1)
+        .fold(snapshot.next_entry_id, u64::max);
+    let next_reconciliation_id = snapshot
+        .reconciliations
+        .iter()
+        .map(|reconciliation| reconciliation.id + 1)
+        .fold(snapshot.next_reconciliation_id, u64::max);
+    ID_COUNTER.with(|counter| {
+        counter
+            .borrow_mut()
+            .set(next_entry_id)
+            .expect("Cannot update id counter")
+    });
+    RECONCILIATION_ID_COUNTER.with(|counter| {
+        counter
+            .borrow_mut()
+            .set(next_reconciliation_id)
+            .expect("Cannot update reconciliation counter")
+    });
+    BALANCE.with(|balance| {
+        balance
+            .borrow_mut()
+            .set(snapshot.balance)
+            .expect("Cannot update balance")
+    });
+    set_allocated(snapshot.allocated);
+
+    let current = get_settings();
+    update_settings(|settings| {
+        *settings = Settings {
+            admin: current.admin,
+            pending_admin: current.pending_admin,
+            initialized: current.initialized,
+            destructive_token: None,
+            ..snapshot.settings
+        }
+    });
+
+    log_audit(
+        AuditAction::Restore,
+        None,
+        format!(
+            "Imported snapshot taken at {} with {} entries, replacing {}",
+            snapshot.taken_at,
+            snapshot.entries.len(),
+            ids.len()
+        ),
+    );
+    Ok(snapshot.entries.len() as u64)
+}
+
+// Recomputes every cached counter from the stored entries in a single pass
+// and overwrites the ones that drifted. Returns only the corrected counters.
+#[ic_cdk::update]
+fn repair_counters() -> Result<Vec<CounterRepair>, Error> {
+    ensure_admin()?;
+
+    let (balance, next_id) = PETTY_CASH_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .fold((0.0, 0), |(balance, next_id), (id, entry)| {
+                let change = if entry.scheduled {
+                    0.0
+                } else {
+                    balance_change(&entry)
+                };
+                (balance + change, next_id.max(id + 1))
+            })
+    });
+
+    let mut repairs = Vec::new();
+
+    let previous_balance = raw_balance();
+    if !amount_equals(previous_balance, balance) {
+        BALANCE.with(|cell| {
+            cell.borrow_mut()
+                .set(balance)
+                .expect("Cannot update balance")
+        });
+        repairs.push(CounterRepair {
+            counter: "balance".to_string(),
+            previous: previous_balance,
+            recomputed: balance,
+        });
+    }
+
+    // The id counter is only ever moved forward so deleted ids are not reused
+    let previous_id = ID_COUNTER.with(|counter| *counter.borrow().get());
+    if previous_id < next_id {
+        ID_COUNTER
+            .with(|counter| counter.borrow_mut().set(next_id))
+            .expect("cannot update id counter");
+        repairs.push(CounterRepair {
+            counter: "id_counter".to_string(),
+            previous: previous_id as f64,
+            recomputed: next_id as f64,
+        });
+    }
+
+    for repair in &repairs {
+        log_audit(
+            AuditAction::Repair,
+            None,
+            format!(
+                "{} corrected from {} to {}",
+                repair.counter, repair.previous, repair.recomputed
+            ),
+        );
+    }
+
+    Ok(repairs)
+}
+
+// Bounded depth-first search behind find_entries_summing_to
+struct SumSearch<'a> {
+    candidates: &'a [(u64, f64)],
+    target: f64,
+    tolerance: f64,
+    max_combination: usize,
+    steps: u64,
+    chosen: Vec<u64>,
+    results: Vec<Vec<u64>>,
+}
+
+impl SumSearch<'_> {
+    fn run(&mut self, start: usize, sum: f64) {
+        for index in start..self.candidates.len() {
+            if self.results.len() >= MAX_SUM_RESULTS || self.steps >= MAX_SUM_STEPS {
+                return;
+            }
+            self.steps += 1;
+
+            let (id, amount) = self.candidates[index];
+            let sum = sum + amount;
+            if sum > self.target + self.tolerance {
+                return;
+            }
+
+            self.chosen.push(id);
+            if (sum - self.target).abs() <= self.tolerance {
+                let mut ids = self.chosen.clone();
+                ids.sort_unstable();
+                self.results.push(ids);
+            }
+            if self.chosen.len() < self.max_combination {
+                self.run(index + 1, sum);
+            }
+            self.chosen.pop();
+        }
+    }
+}
+
+// Helper functions
+fn next_entry_id() -> u64 {
+    ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter")
+}
+
+fn do_insert(entry: &PettyCashEntry) {
+    PETTY_CASH_STORAGE.with(|service| {
+        service.borrow_mut().insert(entry.id, entry.clone())
+    });
+}
+
+fn _get_entry(id: &u64) -> Option<PettyCashEntry> {
+    PETTY_CASH_STORAGE.with(|service| service.borrow().get(id))
+}
+
+fn entry_status(entry: &PettyCashEntry) -> EntryStatus {
+    if entry.scheduled {
+        EntryStatus::Scheduled
+    } else if entry.approved_by.is_some() {
+        EntryStatus::Approved
+    } else {
+        EntryStatus::Pending
+    }
+}
+
+// Signed effect of an entry on the balance
+fn balance_change(entry: &PettyCashEntry) -> f64 {
+    match entry.entry_type {
+        TransactionType::Credit => entry.amount,
+        TransactionType::Debit => -entry.amount,
+    }
+}
+
+// Net balance change of giving an entry a new type and amount: its previous
+// change is reversed and the new one applied. Scheduled entries never touch
+// the balance, before or after the update.
+fn update_balance_delta(
+    entry: &PettyCashEntry,
+    entry_type: TransactionType,
+    amount: f64,
+    scheduled: bool,
+) -> f64 {
+    let old_change = if entry.scheduled {
+        0.0
+    } else {
+        balance_change(entry)
+    };
+    let new_change = if scheduled {
+        0.0
+    } else {
+        match entry_type {
+            TransactionType::Credit => amount,
+            TransactionType::Debit => -amount,
+        }
+    };
+    new_change - old_change
+}
+
+fn violates_description_policy(
+    policy: &DescriptionPolicy,
+    entry_type: TransactionType,
+    amount: f64,
+    description: &str,
+) -> bool {
+    matches!(entry_type, TransactionType::Debit)
+        && amount > policy.min_amount
+        && description.trim().chars().count() < policy.min_length as usize
+}
+
+fn ensure_description_policy(
+    entry_type: TransactionType,
+    amount: f64,
+    description: &str,
+) -> Result<(), Error> {
+    if let Some(policy) = get_settings().description_policy {
+        if violates_description_policy(&policy, entry_type, amount, description) {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "Debits above {} need a description of at least {} characters",
+                    policy.min_amount, policy.min_length
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn validate_entry_fields(payload: &EntryPayload, attachments: &[Attachment]) -> Result<(), Error> {
+    let limits = [
+        (
+            "Description",
+            Some(payload.description.as_str()),
+            MAX_DESCRIPTION_LEN,
+        ),
+        (
+            "Category",
+            Some(payload.category.as_str()),
+            MAX_CATEGORY_LEN,
+        ),
+        (
+            "Receipt url",
+            payload.receipt_url.as_deref(),
+            MAX_RECEIPT_URL_LEN,
+        ),
+        (
+            "Client id",
+            payload.billable_to.as_deref(),
+            MAX_CLIENT_ID_LEN,
+        ),
+    ];
+    for (field, value, max) in limits {
+        if matches!(value, Some(value) if value.len() > max) {
+            return Err(Error::InvalidInput {
+                msg: format!("{} can be at most {} bytes", field, max),
+            });
+        }
+    }
+    validate_attachments(attachments)?;
+
+    let entry = PettyCashEntry {
+        description: payload.description.clone(),
+        category: payload.category.clone(),
+        receipt_url: payload.receipt_url.clone(),
+        attachments: attachments.to_vec(),
+        billable_to: payload.billable_to.clone(),
+        ..Default::default()
+    };
+    if !entry_fits(&entry) {
+        return Err(Error::InvalidInput {
+            msg: format!(
+                "Entry would exceed the storage limit of {} bytes",
+                PettyCashEntry::MAX_SIZE
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Whether the entry stays within PettyCashEntry::MAX_SIZE once it has been
+// approved with the longest approver and signature, so approving never traps
+fn entry_fits(entry: &PettyCashEntry) -> bool {
+    let mut entry = entry.clone();
+    entry
+        .approved_by
+        .get_or_insert_with(|| "x".repeat(MAX_PRINCIPAL_TEXT_LEN));
+    entry
+        .approval_signature
+        .get_or_insert_with(|| "0".repeat(MAX_SIGNATURE_LEN));
+    entry.approved_at.get_or_insert(0);
+    entry.updated_at.get_or_insert(0);
+    entry.reconciliation_id.get_or_insert(0);
+    Encode!(&entry).map_or(false, |bytes| {
+        bytes.len() <= PettyCashEntry::MAX_SIZE as usize
+    })
+}
+
+fn validate_attachments(attachments: &[Attachment]) -> Result<(), Error> {
+    if attachments.len() > MAX_ATTACHMENTS {
+        return Err(Error::InvalidInput {
+            msg: format!("At most {} attachments are allowed", MAX_ATTACHMENTS),
+        });
+    }
+
+    for attachment in attachments {
+        if attachment.url.len() > MAX_ATTACHMENT_URL_LEN || !is_valid_url(&attachment.url) {
+            return Err(Error::InvalidInput {
+                msg: format!("Invalid attachment url: {}", attachment.url),
+            });
+        }
+        if attachment.filename.trim().is_empty()
+            || attachment.filename.len() > MAX_ATTACHMENT_FILENAME_LEN
+        {
+            return Err(Error::InvalidInput {
+                msg: format!(
+                    "Attachment filename must be 1 to {} bytes",
+                    MAX_ATTACHMENT_FILENAME_LEN
+                ),
+            });
+        }
+        if attachment.mime_type.len() > MAX_MIME_TYPE_LEN
+            || !is_valid_mime_type(&attachment.mime_type)
+        {
+            return Err(Error::InvalidInput {
+                msg: format!("Invalid attachment mime type: {}", attachment.mime_type),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Trims the url and lowercases its scheme and host, the path is case sensitive
+fn normalize_url(url: &str) -> String {
+    let url = url.trim();
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (format!("{}://", scheme.to_lowercase()), rest),
+        None => (String::new(), url),
+    };
+    let host_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (host, path) = rest.split_at(host_end);
+    format!("{}{}{}", scheme, host.to_lowercase(), path)
+}
+
+fn is_valid_signature(signature: &str) -> bool {
+    matches!(signature.len(), 64 | 128) && signature.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// http(s) url with a non-empty host and no whitespace
+fn is_valid_url(url: &str) -> bool {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"));
+    match rest {
+        Some(rest) => {
+            let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+            !host.is_empty() && !url.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+// type/subtype made of RFC 6838 token characters, e.g. image/png
+fn is_valid_mime_type(mime_type: &str) -> bool {
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+    match mime_type.split_once('/') {
+        Some((kind, subtype)) => is_token(kind) && is_token(subtype),
+        None => false,
+    }
+}
+
+fn log_audit(action: AuditAction, entry_id: Option<u64>, details: impl Into<String>) {
+    insert_audit_record(action, entry_id, details, None);
+}
+
+fn insert_audit_record(
+    action: AuditAction,
+    entry_id: Option<u64>,
+    details: impl Into<String>,
+    amount_change: Option<(f64, f64)>,
+) {
+    let id = AUDIT_ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment audit counter");
+
+    // Keep the record within AuditRecord::MAX_SIZE
+    let details = truncate_bytes(&details.into(), MAX_AUDIT_DETAILS_LEN);
+    let record = AuditRecord {
+        id,
+        action,
+        entry_id,
+        caller: caller(),
+        timestamp: time(),
+        details,
+        amount_change,
+    };
+    AUDIT_LOG.with(|log| log.borrow_mut().insert(id, record));
+}
+
+// Longest prefix of text that fits in max bytes without splitting a character
+fn truncate_bytes(text: &str, max: usize) -> String {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+fn get_settings() -> Settings {
+    SETTINGS.with(|settings| settings.borrow().get().clone())
+}
+
+fn update_settings(f: impl FnOnce(&mut Settings)) {
+    SETTINGS.with(|cell| {
+        let mut settings = cell.borrow().get().clone();
+        f(&mut settings);
+        cell.borrow_mut()
+            .set(settings)
+            .expect("Cannot update settings");
+    });
+}
+
+fn ensure_initialized() -> Result<(), Error> {
+    if get_settings().initialized {
+        Ok(())
+    } else {
+        Err(Error::NotInitialized {
+            msg: "The canister has no admin yet, call set_admin first".to_string(),
+        })
+    }
+}
+
+fn ensure_admin() -> Result<(), Error> {
+    if get_settings().admin == Some(caller()) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: "Only the admin can perform this action".to_string(),
+        })
+    }
+}
+
+// Checks the token from request_destructive_token and invalidates it
+fn consume_destructive_token(token: &str) -> Result<(), Error> {
+    let stored = get_settings().destructive_token;
+    let valid = match &stored {
+        Some(stored) => {
+            stored.token == token && stored.owner == caller() && time() <= stored.expires_at
+        }
+        None => false,
+    };
+    if !valid {
+        return Err(Error::Unauthorized {
+            msg: "Invalid or expired confirmation token".to_string(),
+        });
+    }
+
+    update_settings(|settings| settings.destructive_token = None);
+    Ok(())
+}
+
+fn ensure_editable(entry: &PettyCashEntry) -> Result<(), Error> {
+    if entry.locked {
+        return Err(Error::EntryNotEditable {
+            msg: format!("Entry with id={} is locked", entry.id),
+        });
+    }
+    ensure_period_open(entry.date)
+}
+
+// Rejects an entry that would exceed its category's monthly entry limit.
+// exclude_id skips the entry being updated so it is not counted twice.
+fn ensure_category_capacity(
+    category: &str,
+    date: u64,
+    exclude_id: Option<u64>,
+) -> Result<(), Error> {
+    let category = normalize_category(category);
+    let limit = match get_settings().category_entry_limits.get(&category) {
+        Some(limit) => *limit,
+        None => return Ok(()),
+    };
+
+    let (year, month) = month_of(date);
+    let (start_date, end_date) = month_bounds(year, month);
+    let count = PETTY_CASH_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .filter(|(id, entry)| {
+                Some(*id) != exclude_id
+                    && entry.date >= start_date
+                    && entry.date <= end_date
+                    && normalize_category(&entry.category) == category
+            })
+            .count() as u64
+    });
+
+    if count >= limit {
+        return Err(Error::RateLimited {
+            msg: format!(
+                "Category {} already has {} entries in {}-{:02}, the limit is {}",
+                category, count, year, month, limit
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Entries dated in a closed period may only be touched by the admin
+fn ensure_period_open(date: u64) -> Result<(), Error> {
+    let (year, month, _) = civil_from_days(day_index(date));
+    let settings = get_settings();
+    if settings.closed_periods.contains_key(&(year as u32, month))
+        && settings.admin != Some(caller())
+    {
+        return Err(Error::EntryNotEditable {
+            msg: format!("Period {}-{:02} is closed", year, month),
+        });
+    }
+    Ok(())
+}
+
+fn validate_period(year: u32, month: u32) -> Result<(), Error> {
+    if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+        return Err(Error::InvalidInput {
+            msg: format!("Year must be between {} and {}", MIN_YEAR, MAX_YEAR),
+        });
+    }
+    if !(1..=12).contains(&month) {
+        return Err(Error::InvalidInput {
+            msg: format!("Invalid month {}", month),
+        });
+    }
+    Ok(())
+}
+
+fn compute_monthly_report(year: u32, month: u32) -> MonthlyReport {
+    let (start_date, end_date) = month_bounds(year as u64, month);
+    let mut report = MonthlyReport {
+        year,
+        month,
+        total_debit: 0.0,
+        total_credit: 0.0,
+        entry_count: 0,
+        closing_balance: 0.0,
+        from_snapshot: false,
+    };
+
+    PETTY_CASH_STORAGE.with(|storage| {
+        for (_, entry) in storage.borrow().iter() {
+            if entry.scheduled || entry.date > end_date {
+                continue;
+            }
+            report.closing_balance += balance_change(&entry);
+            if entry.date >= start_date {
+                report.entry_count += 1;
+                match entry.entry_type {
+                    TransactionType::Debit => report.total_debit += entry.amount,
+                    TransactionType::Credit => report.total_credit += entry.amount,
+                }
+            }
+        }
+    });
+
+    report
+}
+
+fn store_period_snapshot(year: u32, month: u32) -> MonthlyReport {
+    let snapshot = MonthlyReport {
+        from_snapshot: true,
+        ..compute_monthly_report(year, month)
+    };
+    let period = ClosedPeriod {
+        closed_at: time(),
+        closed_by: caller(),
+        snapshot: snapshot.clone(),
+    };
+    update_settings(|settings| {
+        settings.closed_periods.insert((year, month), period);
+    });
+    snapshot
+}
+
+fn ensure_approver(category: &str) -> Result<(), Error> {
+    if is_approver_for(&get_settings(), caller(), category) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: format!("Caller is not an approver for category {}", category),
+        })
+    }
+}
+
+// A category's designated approver, or when it has none, the admin or any
+// designated category approver
+fn is_approver_for(settings: &Settings, principal: Principal, category: &str) -> bool {
+    match settings
+        .category_approvers
+        .get(&normalize_category(category))
+    {
+        Some(approver) => *approver == principal,
+        None => {
+            settings.admin == Some(principal)
+                || settings
+                    .category_approvers
+                    .values()
+                    .any(|approver| *approver == principal)
+        }
+    }
+}
+
+// Whether following parents from some category never ends
+fn has_category_cycle(parents: &BTreeMap<String, String>) -> bool {
+    parents.keys().any(|category| {
+        let mut current = category;
+        for _ in 0..parents.len() {
+            match parents.get(current) {
+                Some(parent) => current = parent,
+                None => return false,
+            }
+        }
+        true
+    })
+}
+
+// Total debits per normalized category
+fn debits_by_category(start_date: u64, end_date: u64) -> BTreeMap<String, f64> {
+    let mut spent: BTreeMap<String, f64> = BTreeMap::new();
+    for entry in get_entries_by_date_range(start_date, end_date) {
+        if matches!(entry.entry_type, TransactionType::Debit) {
+            *spent
+                .entry(normalize_category(&entry.category))
+                .or_default() += entry.amount;
+        }
+    }
+    spent
+}
+
+// Client ids match ignoring case and surrounding whitespace, blank means none
+fn normalize_client(client: Option<String>) -> Option<String> {
+    client
+        .map(|client| client.trim().to_lowercase())
+        .filter(|client| !client.is_empty())
+}
+
+fn normalize_category(category: &str) -> String {
+    category.trim().to_lowercase()
+}
+
+// Division that returns 0 instead of NaN or infinity
+fn safe_div(numerator: f64, denominator: f64) -> f64 {
+    if denominator == 0.0 {
+        return 0.0;
+    }
+    let result = numerator / denominator;
+    if result.is_finite() {
+        result
+    } else {
+        0.0
+    }
+}
+
+// Number of whole UTC days since the unix epoch
+fn day_index(timestamp: u64) -> u64 {
+    timestamp / NANOS_PER_DAY
+}
+
+// UTC hour (0-23) of a timestamp
+fn hour_of_day(timestamp: u64) -> usize {
+    ((timestamp % NANOS_PER_DAY) / (NANOS_PER_DAY / 24)) as usize
+}
+
+// Converts days since the unix epoch into a (year, month, day) UTC date
+fn civil_from_days(days: u64) -> (u64, u32, u32) {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year as u64, month, day)
+}
+
+// Inverse of civil_from_days
+fn days_from_civil(year: u64, month: u32, day: u32) -> u64 {
+    let year = year as i64 - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * 146_097 + doe - 719_468) as u64
+}
+
+// UTC (year, month) of a timestamp
+fn month_of(timestamp: u64) -> (u64, u32) {
+    let (year, month, _) = civil_from_days(day_index(timestamp));
+    (year, month)
+}
+
+// First and last nanosecond of a UTC calendar month
+fn month_bounds(year: u64, month: u32) -> (u64, u64) {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    let start = days_from_civil(year, month, 1) * NANOS_PER_DAY;
+    let end = days_from_civil(next_year, next_month, 1) * NANOS_PER_DAY - 1;
+    (start, end)
+}
+
+fn format_date(timestamp: u64, date_format: DateFormat) -> String {
+    let (year, month, day) = civil_from_days(day_index(timestamp));
+    match date_format {
+        DateFormat::Iso8601 => {
+            let seconds = (timestamp % NANOS_PER_DAY) / 1_000_000_000;
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                year,
+                month,
+                day,
+                seconds / 3_600,
+                seconds % 3_600 / 60,
+                seconds % 60
+            )
+        }
+        DateFormat::DdMmYyyy => format!("{:02}/{:02}/{:04}", day, month, year),
+        DateFormat::MmDdYyyy => format!("{:02}/{:02}/{:04}", month, day, year),
+    }
+}
+
+fn export_row(entry: &PettyCashEntry, date_format: DateFormat) -> ExportRow {
+    ExportRow {
+        id: entry.id,
+        date: format_date(entry.date, date_format),
+        description: entry.description.clone(),
+        amount: entry.amount,
+        entry_type: format!("{:?}", entry.entry_type),
+        category: entry.category.clone(),
+        receipt_url: entry.receipt_url.clone(),
+        approved_by: entry.approved_by.clone(),
+        approval_signature: entry.approval_signature.clone(),
+        attachments: entry.attachments.clone(),
+        billable_to: entry.billable_to.clone(),
+    }
+}
+
+// Quotes a CSV field when it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn raw_balance() -> f64 {
+    BALANCE.with(|balance| *balance.borrow().get())
+}
+
+fn raw_allocated() -> f64 {
+    ALLOCATED.with(|allocated| *allocated.borrow().get())
+}
+
+fn raw_available() -> f64 {
+    raw_balance() - raw_allocated()
+}
+
+// Rounds half away from zero to the configured display precision, which
+// removes float noise such as 0.30000000000000004 from exposed balances
+fn round_for_display(value: f64) -> f64 {
+    let decimals = get_settings()
+        .display_precision
+        .unwrap_or(DEFAULT_DISPLAY_PRECISION);
+    let factor = 10f64.powi(decimals as i32);
+    let rounded = (value * factor).round() / factor;
+    // Avoid exposing -0.0
+    if rounded == 0.0 {
+        0.0
+    } else {
+        rounded
+    }
+}
+
+// Half of the smallest unit shown at the display precision. Amounts closer
+// than this are indistinguishable once rounded for display.
+fn amount_epsilon() -> f64 {
+    let decimals = get_settings()
+        .display_precision
+        .unwrap_or(DEFAULT_DISPLAY_PRECISION);
+    0.5 / 10f64.powi(decimals as i32)
+}
+
+// Float-safe equality for matching user supplied amounts, so that e.g.
+// 0.1 + 0.2 matches 0.3. Whether a stored amount changed is compared exactly.
+fn amount_equals(a: f64, b: f64) -> bool {
+    (a - b).abs() < amount_epsilon()
+}
+
+// Reports and reconciliations are stored with raw balances and rounded only
+// when they are returned
+fn report_for_display(mut report: MonthlyReport) -> MonthlyReport {
+    report.closing_balance = round_for_display(report.closing_balance);
+    report
+}
+
+fn reconciliation_for_display(mut reconciliation: Reconciliation) -> Reconciliation {
+    reconciliation.expected_balance = round_for_display(reconciliation.expected_balance);
+    reconciliation.difference = round_for_display(reconciliation.difference);
+    reconciliation
+}
+
+// Lowest balance a debit may leave: the minimum reserve plus the allocated
+// amount when available balance enforcement is on
+fn reserved_balance() -> f64 {
+    let settings = get_settings();
+    if settings.enforce_available_balance {
+        settings.minimum_reserve + raw_allocated()
+    } else {
+        settings.minimum_reserve
+    }
+}
+
+fn set_allocated(amount: f64) {
+    ALLOCATED.with(|allocated| {
+        allocated
+            .borrow_mut()
+            .set(amount)
+            .expect("Cannot update allocated balance")
+    });
+}
+
+fn apply_balance_change(change: f64) {
+    BALANCE.with(|balance| {
+        let current_balance = *balance.borrow().get();
+        balance
+            .borrow_mut()
+            .set(current_balance + change)
+            .expect("Cannot update balance");
+    });
 }
 
 // Generate Candid interface
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_entries_decode_with_defaults() {
+        let legacy = LegacyPettyCashEntry {
+            id: 7,
+            date: 1_000,
+            description: "Parking".to_string(),
+            amount: 12.5,
+            entry_type: TransactionType::Credit,
+            category: "transport".to_string(),
+            receipt_url: None,
+            approved_by: Some("manager".to_string()),
+            created_at: 900,
+            updated_at: None,
+        };
+        let bytes = Encode!(&legacy).unwrap();
+
+        let entry = PettyCashEntry::from_bytes(Cow::Owned(bytes));
+        assert_eq!(entry.id, 7);
+        assert_eq!(entry.amount, 12.5);
+        assert_eq!(entry.entry_type, TransactionType::Credit);
+        assert_eq!(entry.approved_by.as_deref(), Some("manager"));
+        assert!(!entry.scheduled);
+        assert!(!entry.locked);
+        assert!(entry.attachments.is_empty());
+    }
+
+    #[test]
+    fn current_entries_round_trip() {
+        let entry = PettyCashEntry {
+            id: 3,
+            scheduled: true,
+            locked: true,
+            ..Default::default()
+        };
+        let decoded = PettyCashEntry::from_bytes(entry.to_bytes());
+        assert!(decoded.scheduled);
+        assert!(decoded.locked);
+    }
+
+    #[test]
+    fn audit_details_fit_the_record() {
+        let details = truncate_bytes(
+            &"\u{4e2d}".repeat(MAX_AUDIT_DETAILS_LEN),
+            MAX_AUDIT_DETAILS_LEN,
+        );
+        assert!(details.len() <= MAX_AUDIT_DETAILS_LEN);
+        assert!(details.chars().all(|c| c == '\u{4e2d}'));
+
+        let record = AuditRecord {
+            id: u64::MAX,
+            action: AuditAction::CorrectAmount,
+            entry_id: Some(u64::MAX),
+            caller: Principal::from_slice(&[0xff; 29]),
+            timestamp: u64::MAX,
+            details,
+            amount_change: Some((f64::MAX, f64::MIN)),
+        };
+        assert!(record.to_bytes().len() <= AuditRecord::MAX_SIZE as usize);
+    }
+
+    #[test]
+    fn reconciliation_notes_fit_the_record() {
+        let reconciliation = Reconciliation {
+            id: u64::MAX,
+            counted_amount: f64::MAX,
+            expected_balance: f64::MAX,
+            difference: f64::MIN,
+            note: truncate_bytes(
+                &"\u{4e2d}".repeat(MAX_AUDIT_DETAILS_LEN),
+                MAX_AUDIT_DETAILS_LEN,
+            ),
+            reconciled_at: u64::MAX,
+            reconciled_by: Principal::from_slice(&[0xff; 29]),
+        };
+        assert!(reconciliation.to_bytes().len() <= Reconciliation::MAX_SIZE as usize);
+    }
+
+    #[test]
+    fn category_cycles_are_detected() {
+        let mut parents = BTreeMap::new();
+        parents.insert("flights".to_string(), "travel".to_string());
+        parents.insert("hotels".to_string(), "travel".to_string());
+        assert!(!has_category_cycle(&parents));
+
+        parents.insert("travel".to_string(), "flights".to_string());
+        assert!(has_category_cycle(&parents));
+
+        parents.clear();
+        parents.insert("travel".to_string(), "travel".to_string());
+        assert!(has_category_cycle(&parents));
+    }
+
+    #[test]
+    fn periods_outside_the_timestamp_range_are_rejected() {
+        assert!(validate_period(1970, 1).is_ok());
+        assert!(validate_period(MAX_YEAR, 12).is_ok());
+        assert!(validate_period(1969, 12).is_err());
+        assert!(validate_period(MAX_YEAR + 1, 1).is_err());
+        assert!(validate_period(2024, 13).is_err());
+
+        let (_, end) = month_bounds(MAX_YEAR as u64, 12);
+        assert_eq!(
+            format_date(end, DateFormat::Iso8601),
+            "2553-12-31T23:59:59Z"
+        );
+    }
+
+    // 2024-03-31T14:05:09Z
+    const SAMPLE_TIMESTAMP: u64 = 1_711_893_909_000_000_000;
+
+    #[test]
+    fn formats_dates_in_each_format() {
+        assert_eq!(
+            format_date(SAMPLE_TIMESTAMP, DateFormat::Iso8601),
+            "2024-03-31T14:05:09Z"
+        );
+        assert_eq!(
+            format_date(SAMPLE_TIMESTAMP, DateFormat::DdMmYyyy),
+            "31/03/2024"
+        );
+        assert_eq!(
+            format_date(SAMPLE_TIMESTAMP, DateFormat::MmDdYyyy),
+            "03/31/2024"
+        );
+        assert_eq!(format_date(0, DateFormat::Iso8601), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_date(0, DateFormat::default()),
+            "1970-01-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn civil_dates_round_trip() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        // Leap days, century and 400 year rules
+        assert_eq!(civil_from_days(days_from_civil(2024, 2, 29)), (2024, 2, 29));
+        assert_eq!(civil_from_days(days_from_civil(2000, 2, 29)), (2000, 2, 29));
+        assert_eq!(
+            days_from_civil(2100, 3, 1) - days_from_civil(2100, 2, 28),
+            1
+        );
+        assert_eq!(
+            days_from_civil(2024, 3, 1) - days_from_civil(2024, 2, 28),
+            2
+        );
+
+        for days in (0..200_000).step_by(17) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn month_bounds_cover_the_whole_month() {
+        let (start, end) = month_bounds(2024, 2);
+        assert_eq!(
+            format_date(start, DateFormat::Iso8601),
+            "2024-02-01T00:00:00Z"
+        );
+        assert_eq!(
+            format_date(end, DateFormat::Iso8601),
+            "2024-02-29T23:59:59Z"
+        );
+        assert_eq!(month_of(end + 1), (2024, 3));
+
+        let (_, end) = month_bounds(2023, 12);
+        assert_eq!(month_of(end + 1), (2024, 1));
+    }
+
+    #[test]
+    fn displayed_balance_hides_float_noise() {
+        let mut exact = 0.0;
+        for i in 0..1_000 {
+            let change = if i % 4 == 3 { -0.07 } else { 0.1 };
+            exact += change;
+            apply_balance_change(change);
+        }
+
+        // Internal arithmetic keeps the raw sum, noise included
+        assert_eq!(raw_balance(), exact);
+        assert_ne!(raw_balance(), 57.5);
+        assert_eq!(get_current_balance(), 57.5);
+        assert_eq!(get_current_balance().to_string(), "57.5");
+    }
+
+    #[test]
+    fn display_precision_is_configurable() {
+        apply_balance_change(1.0 / 3.0);
+        assert_eq!(get_current_balance(), 0.33);
+
+        update_settings(|settings| settings.display_precision = Some(4));
+        assert_eq!(get_current_balance(), 0.3333);
+        assert_eq!(raw_balance(), 1.0 / 3.0);
+
+        // Rounds half away from zero and never shows -0
+        update_settings(|settings| settings.display_precision = Some(0));
+        assert_eq!(round_for_display(2.5), 3.0);
+        assert_eq!(round_for_display(-2.5), -3.0);
+        assert!(round_for_display(-0.2).is_sign_positive());
+    }
+
+    #[test]
+    fn amounts_match_within_the_display_precision() {
+        assert_ne!(0.1 + 0.2, 0.3);
+        assert!(amount_equals(0.1 + 0.2, 0.3));
+        assert!(amount_equals(1.1 + 2.2, 3.3));
+        assert!(amount_equals((0..10).map(|_| 0.1).sum(), 1.0));
+        assert!(!amount_equals(0.3, 0.31));
+        assert!(!amount_equals(10.0, 10.01));
+
+        update_settings(|settings| settings.display_precision = Some(0));
+        assert!(amount_equals(10.0, 10.4));
+        assert!(!amount_equals(10.0, 10.6));
+    }
+
+    #[test]
+    fn verify_entry_uses_amount_equality() {
+        do_insert(&PettyCashEntry {
+            id: 1,
+            amount: 0.1 + 0.2,
+            ..Default::default()
+        });
+
+        let result = verify_entry(1, 0.3);
+        assert!(result.exists && result.amount_matches);
+        let result = verify_entry(1, 0.4);
+        assert!(result.exists && !result.amount_matches);
+        let result = verify_entry(2, 0.3);
+        assert!(!result.exists && !result.amount_matches);
+    }
+
+    fn entry(entry_type: TransactionType, amount: f64, scheduled: bool) -> PettyCashEntry {
+        PettyCashEntry {
+            amount,
+            entry_type,
+            scheduled,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn type_flip_swings_balance_by_twice_the_amount() {
+        let debit = entry(TransactionType::Debit, 40.0, false);
+        assert_eq!(
+            update_balance_delta(&debit, TransactionType::Credit, 40.0, false),
+            80.0
+        );
+
+        let credit = entry(TransactionType::Credit, 25.0, false);
+        assert_eq!(
+            update_balance_delta(&credit, TransactionType::Debit, 25.0, false),
+            -50.0
+        );
+
+        // Flipping and changing the amount at once
+        assert_eq!(
+            update_balance_delta(&debit, TransactionType::Credit, 10.0, false),
+            50.0
+        );
+        assert_eq!(
+            update_balance_delta(&credit, TransactionType::Debit, 5.0, false),
+            -30.0
+        );
+
+        // A non-flipping update only moves the balance by the difference
+        assert_eq!(
+            update_balance_delta(&debit, TransactionType::Debit, 30.0, false),
+            10.0
+        );
+    }
+
+    #[test]
+    fn type_flip_ignores_scheduled_entries() {
+        let scheduled_debit = entry(TransactionType::Debit, 40.0, true);
+        assert_eq!(
+            update_balance_delta(&scheduled_debit, TransactionType::Credit, 40.0, false),
+            40.0
+        );
+        assert_eq!(
+            update_balance_delta(&scheduled_debit, TransactionType::Credit, 40.0, true),
+            0.0
+        );
+
+        let debit = entry(TransactionType::Debit, 40.0, false);
+        assert_eq!(
+            update_balance_delta(&debit, TransactionType::Credit, 40.0, true),
+            40.0
+        );
+    }
+
+    #[test]
+    fn flipping_back_restores_the_balance() {
+        apply_balance_change(100.0);
+        let mut debit = entry(TransactionType::Debit, 40.0, false);
+        apply_balance_change(balance_change(&debit));
+        assert_eq!(raw_balance(), 60.0);
+
+        apply_balance_change(update_balance_delta(
+            &debit,
+            TransactionType::Credit,
+            40.0,
+            false,
+        ));
+        assert_eq!(raw_balance(), 140.0);
+
+        debit.entry_type = TransactionType::Credit;
+        apply_balance_change(update_balance_delta(
+            &debit,
+            TransactionType::Debit,
+            40.0,
+            false,
+        ));
+        assert_eq!(raw_balance(), 60.0);
+    }
+
+    #[test]
+    fn payloads_without_the_newer_fields_decode() {
+        #[derive(candid::CandidType)]
+        struct LegacyPayload {
+            description: String,
+            amount: f64,
+            entry_type: TransactionType,
+            category: String,
+            receipt_url: Option<String>,
+            approved_by: Option<String>,
+        }
+
+        let bytes = Encode!(&LegacyPayload {
+            description: "Stamps".to_string(),
+            amount: 4.0,
+            entry_type: TransactionType::Debit,
+            category: "office".to_string(),
+            receipt_url: None,
+            approved_by: None,
+        })
+        .unwrap();
+        let payload = Decode!(&bytes, EntryPayload).unwrap();
+        assert_eq!(payload.description, "Stamps");
+        assert!(payload.date.is_none());
+        assert!(payload.attachments.is_none());
+        assert!(payload.billable_to.is_none());
+    }
+
+    fn payload(description: &str, attachments: usize) -> EntryPayload {
+        EntryPayload {
+            date: None,
+            description: description.to_string(),
+            amount: 10.0,
+            entry_type: TransactionType::Debit,
+            category: "office".to_string(),
+            receipt_url: None,
+            attachments: Some(
+                (0..attachments)
+                    .map(|i| Attachment {
+                        url: format!(
+                            "https://example.com/{}{}",
+                            i,
+                            "a".repeat(MAX_ATTACHMENT_URL_LEN - 21)
+                        ),
+                        filename: "f".repeat(MAX_ATTACHMENT_FILENAME_LEN),
+                        mime_type: format!("application/{}", "x".repeat(MAX_MIME_TYPE_LEN - 12)),
+                        size_bytes: u64::MAX,
+                    })
+                    .collect(),
+            ),
+            billable_to: None,
+        }
+    }
+
+    #[test]
+    fn oversized_entries_are_rejected() {
+        let valid = payload("Printer paper", MAX_ATTACHMENTS);
+        assert!(validate_entry_fields(&valid, valid.attachments.as_deref().unwrap()).is_ok());
+        let long = payload(&"d".repeat(MAX_DESCRIPTION_LEN + 1), 0);
+        assert!(validate_entry_fields(&long, &[]).is_err());
+
+        // Every field within its limit, but too large in total
+        let largest = payload(&"d".repeat(MAX_DESCRIPTION_LEN), MAX_ATTACHMENTS);
+        assert!(matches!(
+            validate_entry_fields(&largest, largest.attachments.as_deref().unwrap()),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+}
\ No newline at end of file